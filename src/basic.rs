@@ -1,29 +1,16 @@
 use std::os::fd::OwnedFd;
 
 use smithay::{
-    backend::{
-        input::{InputEvent, KeyboardKeyEvent},
-        renderer::{
-            Color32F, Frame, Renderer,
-            element::{
-                Kind,
-                surface::{WaylandSurfaceRenderElement, render_elements_from_surface_tree},
-            },
-            gles::GlesRenderer,
-            utils::{draw_render_elements, on_commit_buffer_handler},
-        },
-        winit::{self, WinitEvent},
-    },
-    delegate_compositor, delegate_data_device, delegate_seat, delegate_shm, delegate_xdg_shell,
-    input::{Seat, SeatHandler, SeatState, keyboard::FilterResult},
-    reexports::wayland_server::{Display, protocol::wl_seat},
-    utils::{Rectangle, Serial, Transform},
+    backend::renderer::utils::{on_commit_buffer_handler, with_renderer_surface_state},
+    desktop::Window,
+    input::{Seat, SeatHandler, SeatState},
+    reexports::wayland_server::protocol::wl_seat,
+    utils::{IsAlive, Serial},
     wayland::{
         buffer::BufferHandler,
-        compositor::{
-            CompositorClientState, CompositorHandler, CompositorState, SurfaceAttributes,
-            TraversalAction, with_surface_tree_downward,
-        },
+        compositor::{CompositorClientState, CompositorHandler, CompositorState},
+        output::OutputHandler,
+        seat::WaylandFocus,
         selection::{
             SelectionHandler,
             data_device::{
@@ -38,14 +25,12 @@ use smithay::{
 };
 use wayland_protocols::xdg::shell::server::xdg_toplevel;
 use wayland_server::{
-    Client, ListeningSocket,
-    backend::{ClientData, ClientId, DisconnectReason},
-    protocol::{
-        wl_buffer,
-        wl_surface::{self, WlSurface},
-    },
+    Client, Resource,
+    protocol::{wl_buffer, wl_surface::WlSurface},
 };
 
+use tracing::info;
+
 use crate::{App, ClientState};
 
 impl BufferHandler for App {
@@ -61,7 +46,11 @@ impl XdgShellHandler for App {
         surface.with_pending_state(|state| {
             state.states.set(xdg_toplevel::State::Activated);
         });
-        surface.send_configure();
+
+        // Placed on its first commit, once the client has finished describing it
+        self.unplaced_windows.retain(|window| window.alive());
+        self.unplaced_windows
+            .push(Window::new_wayland_window(surface));
     }
 
     fn new_popup(&mut self, _surface: PopupSurface, _positioner: PositionerState) {
@@ -108,6 +97,33 @@ impl CompositorHandler for App {
 
     fn commit(&mut self, surface: &WlSurface) {
         on_commit_buffer_handler::<Self>(surface);
+
+        if let Some(index) = self
+            .unplaced_windows
+            .iter()
+            .position(|window| window.wl_surface().as_deref() == Some(surface))
+        {
+            let window = self.unplaced_windows.remove(index);
+            self.place_window(window);
+        }
+
+        if let Some(window) = self
+            .space
+            .elements()
+            .find(|window| window.wl_surface().as_deref() == Some(surface))
+        {
+            window.on_commit();
+        }
+
+        // A browser's first frame takes over from its loading placeholder
+        if !self.placeholders.is_empty()
+            && with_renderer_surface_state(surface, |state| state.buffer().is_some())
+                .unwrap_or(false)
+            && let Some(id) = self.panel_for_surface(surface)
+            && self.placeholders.remove(&id).is_some()
+        {
+            info!(%id, "Webpage drew its first frame");
+        }
     }
 }
 
@@ -117,6 +133,8 @@ impl ShmHandler for App {
     }
 }
 
+impl OutputHandler for App {}
+
 impl SeatHandler for App {
     type KeyboardFocus = WlSurface;
     type PointerFocus = WlSurface;
@@ -126,7 +144,15 @@ impl SeatHandler for App {
         &mut self.seat_state
     }
 
-    fn focus_changed(&mut self, _seat: &Seat<Self>, _focused: Option<&WlSurface>) {}
+    fn focus_changed(&mut self, _seat: &Seat<Self>, focused: Option<&WlSurface>) {
+        match focused {
+            Some(surface) => match self.panel_for_surface(surface) {
+                Some(id) => info!(surface = ?surface.id(), %id, "Keyboard focus changed"),
+                None => info!(surface = ?surface.id(), "Keyboard focus changed"),
+            },
+            None => info!("Keyboard focus cleared"),
+        }
+    }
     fn cursor_image(
         &mut self,
         _seat: &Seat<Self>,
@@ -0,0 +1,170 @@
+use std::{env, path::PathBuf, process, time::Duration};
+
+use crate::input::Shortcut;
+
+/// Which backend drives the outputs. DRM unless `--backend winit` is passed.
+#[derive(Clone, Copy, PartialEq)]
+pub enum BackendKind {
+    /// A window on an existing Wayland or X11 session
+    Winit,
+    /// Directly on a TTY through DRM/KMS and libinput
+    Drm,
+}
+
+/// How `--check` reports problems.
+#[derive(Clone, Copy, PartialEq)]
+pub enum CheckFormat {
+    /// One line per problem on stderr
+    Text,
+    /// A single JSON object on stdout, for CI to annotate the file with
+    Json,
+}
+
+pub struct Args {
+    pub backend: BackendKind,
+    pub config: Option<PathBuf>,
+    pub reload_shortcut: Shortcut,
+    pub quit_shortcut: Shortcut,
+    /// Moves the focused slideshow or stack on to its next item
+    pub next_shortcut: Shortcut,
+    /// Relaunches the focused panel's process, such as its browser
+    pub refresh_shortcut: Shortcut,
+    /// Toggles locked mode, and is the only shortcut that works while locked
+    pub lock_shortcut: Shortcut,
+    /// Saves a full-resolution screenshot of every output into `screenshot_dir`
+    pub screenshot_shortcut: Shortcut,
+    pub screenshot_dir: PathBuf,
+    /// Start locked, whatever the config says
+    pub locked: bool,
+    pub restart_backoff: Duration,
+    /// Give up on a panel after this many consecutive crashes. Unlimited when unset.
+    pub max_restarts: Option<u32>,
+    /// Where to listen for runtime commands, overriding the config's `control_socket`
+    pub control_socket: Option<PathBuf>,
+    /// Validate this config file and exit instead of starting the compositor
+    pub check: Option<PathBuf>,
+    pub check_format: CheckFormat,
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Args {
+            backend: BackendKind::Drm,
+            config: None,
+            reload_shortcut: "Ctrl+Alt+R".parse().expect("Default shortcut is valid"),
+            quit_shortcut: "Ctrl+Alt+Q".parse().expect("Default shortcut is valid"),
+            next_shortcut: "Super+Right".parse().expect("Default shortcut is valid"),
+            refresh_shortcut: "Super+R".parse().expect("Default shortcut is valid"),
+            lock_shortcut: "Ctrl+Alt+Shift+L"
+                .parse()
+                .expect("Default shortcut is valid"),
+            screenshot_shortcut: "Ctrl+Alt+S".parse().expect("Default shortcut is valid"),
+            screenshot_dir: env::temp_dir(),
+            locked: false,
+            restart_backoff: Duration::from_secs(1),
+            max_restarts: None,
+            control_socket: None,
+            check: None,
+            check_format: CheckFormat::Text,
+        }
+    }
+}
+
+impl Args {
+    pub fn parse() -> Self {
+        let mut args = Args::default();
+        let mut argv = env::args().skip(1);
+
+        while let Some(arg) = argv.next() {
+            let (flag, inline) = match arg.split_once('=') {
+                Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+                None => (arg.clone(), None),
+            };
+            let mut value = || {
+                inline
+                    .clone()
+                    .or_else(|| argv.next())
+                    .unwrap_or_else(|| usage_error(&format!("{} requires a value", flag)))
+            };
+
+            match flag.as_str() {
+                "--backend" => {
+                    args.backend = match value().as_str() {
+                        "winit" => BackendKind::Winit,
+                        "drm" => BackendKind::Drm,
+                        other => usage_error(&format!("unknown backend '{}'", other)),
+                    }
+                }
+                "--control-socket" => args.control_socket = Some(PathBuf::from(value())),
+                "--check" => args.check = Some(PathBuf::from(value())),
+                "--format" => {
+                    args.check_format = match value().as_str() {
+                        "text" => CheckFormat::Text,
+                        "json" => CheckFormat::Json,
+                        other => usage_error(&format!("unknown format '{}'", other)),
+                    }
+                }
+                "--config" | "-c" => args.config = Some(PathBuf::from(value())),
+                "--reload-shortcut" => {
+                    args.reload_shortcut = value()
+                        .parse()
+                        .unwrap_or_else(|err: String| usage_error(&err))
+                }
+                "--quit-shortcut" => {
+                    args.quit_shortcut = value()
+                        .parse()
+                        .unwrap_or_else(|err: String| usage_error(&err))
+                }
+                "--next-shortcut" => {
+                    args.next_shortcut = value()
+                        .parse()
+                        .unwrap_or_else(|err: String| usage_error(&err))
+                }
+                "--refresh-shortcut" => {
+                    args.refresh_shortcut = value()
+                        .parse()
+                        .unwrap_or_else(|err: String| usage_error(&err))
+                }
+                "--lock-shortcut" => {
+                    args.lock_shortcut = value()
+                        .parse()
+                        .unwrap_or_else(|err: String| usage_error(&err))
+                }
+                "--screenshot-shortcut" => {
+                    args.screenshot_shortcut = value()
+                        .parse()
+                        .unwrap_or_else(|err: String| usage_error(&err))
+                }
+                "--screenshot-dir" => args.screenshot_dir = PathBuf::from(value()),
+                "--locked" => args.locked = true,
+                "--restart-backoff" => {
+                    let secs = value()
+                        .parse()
+                        .unwrap_or_else(|_| usage_error("--restart-backoff takes whole seconds"));
+                    args.restart_backoff = Duration::from_secs(secs)
+                }
+                "--max-restarts" => {
+                    let count = value()
+                        .parse()
+                        .unwrap_or_else(|_| usage_error("--max-restarts takes a whole number"));
+                    args.max_restarts = Some(count)
+                }
+                _ => usage_error(&format!("unrecognised argument '{}'", arg)),
+            }
+        }
+
+        args
+    }
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("Error: {}", message);
+    eprintln!("Usage: screens [--backend drm|winit] [--config <path>] [--reload-shortcut <chord>]");
+    eprintln!("               [--quit-shortcut <chord>] [--next-shortcut <chord>]");
+    eprintln!("               [--refresh-shortcut <chord>] [--restart-backoff <secs>]");
+    eprintln!("               [--lock-shortcut <chord>] [--locked] [--max-restarts <count>]");
+    eprintln!("               [--control-socket <path>] [--screenshot-shortcut <chord>]");
+    eprintln!("               [--screenshot-dir <path>]");
+    eprintln!("       screens --check <path> [--format text|json]");
+    process::exit(2);
+}
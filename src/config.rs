@@ -0,0 +1,1127 @@
+use std::{
+    collections::{HashMap, HashSet},
+    env, fmt, fs, io,
+    net::{SocketAddr, ToSocketAddrs},
+    path::{Path, PathBuf},
+};
+
+use chrono::format::{Item, StrftimeItems};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::{
+    Deserialize, Deserializer, Serialize,
+    de::{self, IgnoredAny, MapAccess, Visitor},
+};
+use smithay::{
+    reexports::calloop::{LoopHandle, channel},
+    utils::Transform,
+};
+use tracing::{info, instrument};
+
+use crate::{App, Display, PanelId, Root, cli::CheckFormat, draw::Color, layout::SplitSize, text};
+
+/// The whole config file.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct Config {
+    #[serde(default)]
+    pub browser: BrowserConfig,
+    /// Shown wherever no panel draws, such as gaps and before clients first paint
+    #[serde(default)]
+    pub background: Color,
+    /// An image scaled to cover each output, drawn over `background` and behind every panel
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wallpaper: Option<PathBuf>,
+    /// Drawn over webpage panels until their browser window shows its first frame
+    #[serde(default)]
+    pub loading: LoadingConfig,
+    /// Scale and rotation for outputs by name, such as `HDMI-A-1`. Panels are laid out in
+    /// the logical size that results, so a rotated output gets portrait panels.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub outputs: HashMap<String, OutputConfig>,
+    /// Shown on every output no display ends up on, so no screen is left blank. Displays
+    /// without an `output` are dealt out first, so this only fills outputs left over
+    /// after that. Unset, such outputs only show the background.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_display: Option<Display>,
+    /// Outputs that all show the same displays, such as `["DP-1", "DP-2", "DP-3"]`. The
+    /// displays on the first are laid out again on each of the others at their own size,
+    /// with processes of their own.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mirror: Vec<String>,
+    /// How many levels containers may nest. Real layouts stay far shallower, anything past
+    /// this is almost certainly a generated config gone wrong. Defaults to 6.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_depth: Option<usize>,
+    /// The most client processes (browsers, commands, videos) to run at once, across
+    /// every output. Panels past it are left showing an error. Defaults to 32.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_windows: Option<usize>,
+    /// What happens to windows that can't be matched to a panel by app id or process
+    #[serde(default)]
+    pub stray_windows: StrayWindows,
+    /// Where to listen for runtime commands. Defaults to `$XDG_RUNTIME_DIR/screens.sock`.
+    /// Only read at startup.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub control_socket: Option<PathBuf>,
+    /// Serve `dev.screens.Compositor` on the session bus, so desktop tooling can set
+    /// webpages, list panels and reload the config. Off unless set. Only read at startup.
+    #[serde(default)]
+    pub dbus: bool,
+    /// Where the HTTP API listens when built with the `http` feature, such as
+    /// `127.0.0.1:7878` to only let local clients change what's on screen. The API is off
+    /// unless this is set, and any address other than loopback needs `http_token`. Only
+    /// read at startup.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http_address: Option<String>,
+    /// Required as `Authorization: Bearer <token>` on every HTTP request when set. Without
+    /// it, displays that run a program of the caller's choosing are refused over HTTP.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http_token: Option<String>,
+    /// Keep all input from clients, so nobody can navigate away from the configured
+    /// content. The lock shortcut is the only way out. Also accepted as `lock_input`.
+    ///
+    /// While locked, every key press and its release is swallowed unless it's the lock
+    /// shortcut, and pointer motion, buttons and scrolling are dropped before reaching the
+    /// seat. Other shortcuts don't fire until the lock is lifted.
+    #[serde(default, alias = "lock_input")]
+    pub locked: bool,
+    /// Write displays changed over the control socket or HTTP API back to this file, so
+    /// they survive a restart
+    #[serde(default)]
+    pub persist: bool,
+    /// The panel given keyboard focus, such as `"2"` or `"2.1"`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub focus: Option<PanelId>,
+    #[serde(deserialize_with = "unique_ids")]
+    pub displays: HashMap<u32, Root>,
+}
+
+/// How webpages are opened, unless a `Webpage` sets its own `browser` or `args_template`.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct BrowserConfig {
+    /// Which browser's flags to use when no `args_template` is given
+    #[serde(default)]
+    pub kind: Browser,
+    /// Defaults to the executable for `kind`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub executable: Option<String>,
+    /// Arguments with `{url}`, `{width}`, `{height}`, `{x}`, `{y}`, `{class}` and `{profile}`
+    /// substituted at spawn time. Defaults to the flags for `kind`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub args_template: Option<Vec<String>>,
+    /// Appended after the template, e.g. `["--kiosk"]`. Placeholders are substituted here too.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub kiosk_flags: Vec<String>,
+}
+
+/// How one output is presented. Only applies to outputs the DRM backend drives.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub struct OutputConfig {
+    /// How many physical pixels make up a logical one. Defaults to 1.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scale: Option<f64>,
+    #[serde(default)]
+    pub transform: OutputTransform,
+}
+
+/// Counter-clockwise rotation of what's shown on an output, as `wl_output` defines it.
+/// Portrait screens mounted on their side want `90` or `270`.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum OutputTransform {
+    #[default]
+    Normal,
+    #[serde(rename = "90")]
+    Rotate90,
+    #[serde(rename = "180")]
+    Rotate180,
+    #[serde(rename = "270")]
+    Rotate270,
+}
+
+impl From<OutputTransform> for Transform {
+    fn from(transform: OutputTransform) -> Self {
+        match transform {
+            OutputTransform::Normal => Transform::Normal,
+            OutputTransform::Rotate90 => Transform::_90,
+            OutputTransform::Rotate180 => Transform::_180,
+            OutputTransform::Rotate270 => Transform::_270,
+        }
+    }
+}
+
+/// Handling for windows that belong to no panel.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum StrayWindows {
+    /// Map them at the top-left corner of the first output, at whatever size they choose
+    #[default]
+    Place,
+    /// Ask them to close
+    Close,
+}
+
+/// The placeholder shown while a browser starts up.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct LoadingConfig {
+    /// Written across the middle of the panel. Empty for a plain color.
+    #[serde(default = "default_loading_text")]
+    pub text: String,
+    #[serde(default = "default_loading_color")]
+    pub color: Color,
+    #[serde(default)]
+    pub background: Color,
+}
+
+impl Default for LoadingConfig {
+    fn default() -> Self {
+        LoadingConfig {
+            text: default_loading_text(),
+            color: default_loading_color(),
+            background: Color::default(),
+        }
+    }
+}
+
+fn default_loading_text() -> String {
+    "Loading…".to_string()
+}
+
+fn default_loading_color() -> Color {
+    Color::WHITE
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum Browser {
+    #[default]
+    Firefox,
+    Chromium,
+}
+
+impl Browser {
+    pub fn executable(self) -> &'static str {
+        match self {
+            Browser::Firefox => "firefox",
+            Browser::Chromium => "chromium",
+        }
+    }
+
+    /// Arguments that open `{url}` in its own window for the panel.
+    pub fn args_template(self) -> Vec<String> {
+        let args: &[&str] = match self {
+            // Firefox ignores its geometry flags under Wayland, so the window is sized and
+            // placed on its first commit instead
+            Browser::Firefox => &[
+                "--no-remote",
+                "--profile",
+                "{profile}",
+                "--new-window",
+                "{url}",
+                "--class",
+                "{class}",
+            ],
+            // Chromium only talks Wayland when asked to
+            Browser::Chromium => &[
+                "--ozone-platform=wayland",
+                "--app={url}",
+                "--kiosk",
+                "--user-data-dir={profile}",
+                "--class={class}",
+                "--window-position={x},{y}",
+                "--window-size={width},{height}",
+            ],
+        };
+
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+}
+
+impl Config {
+    /// The control socket path from the config, or the default under the runtime dir.
+    pub fn control_socket(&self) -> Option<PathBuf> {
+        self.control_socket.clone().or_else(|| {
+            env::var_os("XDG_RUNTIME_DIR").map(|dir| PathBuf::from(dir).join("screens.sock"))
+        })
+    }
+
+    pub fn max_depth(&self) -> usize {
+        self.max_depth.unwrap_or(6)
+    }
+
+    pub fn max_windows(&self) -> usize {
+        self.max_windows.unwrap_or(32)
+    }
+
+    /// The HTTP API token, unless it's unset or empty.
+    pub fn http_token(&self) -> Option<&str> {
+        self.http_token.as_deref().filter(|token| !token.is_empty())
+    }
+
+    /// Checks the displays and settings for problems that would only surface once running,
+    /// collecting every one rather than stopping at the first.
+    pub fn validate(&self) -> Result<(), Vec<ConfigError>> {
+        let mut errors = App::validate(
+            &self.displays,
+            self.default_display.as_ref(),
+            self.max_depth(),
+        )
+        .err()
+        .unwrap_or_default();
+
+        if let Some(message) = self.http_problem() {
+            errors.push(ConfigError {
+                scope: Scope::Setting("http_address"),
+                message,
+            });
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Anyone who can reach the HTTP API can change what's on screen, so it's only served
+    /// beyond this machine with a token.
+    fn http_problem(&self) -> Option<String> {
+        let address = self.http_address.as_deref()?;
+        let resolved: Vec<SocketAddr> = match address.to_socket_addrs() {
+            Ok(resolved) => resolved.collect(),
+            Err(err) => {
+                return Some(format!(
+                    "'{}' isn't an address to listen on: {}",
+                    address, err
+                ));
+            }
+        };
+
+        let exposed = resolved.iter().any(|address| !address.ip().is_loopback());
+        (exposed && self.http_token().is_none()).then(|| {
+            format!(
+                "'{}' is reachable from other machines, so `http_token` must be set",
+                address
+            )
+        })
+    }
+}
+
+/// A file that's only a map of display ids, as written before the `displays` section.
+#[derive(Deserialize)]
+#[serde(transparent)]
+struct Displays(#[serde(deserialize_with = "unique_ids")] HashMap<u32, Root>);
+
+/// Reads the map of display ids, failing on an id given twice rather than silently keeping
+/// the last. `"1"` and `"01"` count as the same id.
+fn unique_ids<'de, D: Deserializer<'de>>(deserializer: D) -> Result<HashMap<u32, Root>, D::Error> {
+    struct Ids;
+
+    impl<'de> Visitor<'de> for Ids {
+        type Value = HashMap<u32, Root>;
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("a map of display ids to displays")
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            let mut displays = HashMap::new();
+            while let Some((id, root)) = map.next_entry::<u32, Root>()? {
+                if displays.insert(id, root).is_some() {
+                    return Err(de::Error::custom(format!(
+                        "display {} is defined more than once",
+                        id
+                    )));
+                }
+            }
+            Ok(displays)
+        }
+    }
+
+    deserializer.deserialize_map(Ids)
+}
+
+/// Just enough of the file to tell the two formats apart.
+#[derive(Deserialize)]
+struct Shape {
+    displays: Option<IgnoredAny>,
+}
+
+pub enum LoadError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    Toml(toml::de::Error),
+    Yaml(serde_yaml::Error),
+    /// Variables named in `${VAR}` patterns that aren't set
+    Env(Vec<String>),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(err) => write!(f, "{}", err),
+            LoadError::Json(err) => write!(f, "{}", err),
+            LoadError::Toml(err) => write!(f, "{}", err),
+            LoadError::Yaml(err) => write!(f, "{}", err),
+            LoadError::Env(names) => {
+                write!(f, "unset environment variables: {}", names.join(", "))
+            }
+        }
+    }
+}
+
+/// Where in the config a problem was found.
+#[derive(Clone, Copy)]
+pub enum Scope {
+    /// Under the top-level display with this id
+    Display(u32),
+    DefaultDisplay,
+    /// A setting outside of the displays, by name
+    Setting(&'static str),
+}
+
+/// A problem with one display or setting in an otherwise parseable config.
+pub struct ConfigError {
+    pub scope: Scope,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.scope {
+            Scope::Display(id) => write!(f, "display {}: {}", id, self.message),
+            Scope::DefaultDisplay => write!(f, "default display: {}", self.message),
+            Scope::Setting(name) => write!(f, "{}: {}", name, self.message),
+        }
+    }
+}
+
+impl App {
+    /// Checks a parsed config for problems that would only surface once spawned, collecting
+    /// every one rather than stopping at the first. Containers may nest `max_depth` levels.
+    pub(crate) fn validate(
+        displays: &HashMap<u32, Root>,
+        default_display: Option<&Display>,
+        max_depth: usize,
+    ) -> Result<(), Vec<ConfigError>> {
+        let mut errors = Vec::new();
+
+        let mut ids: Vec<u32> = displays.keys().copied().collect();
+        ids.sort_unstable();
+        for id in ids {
+            let display = &displays[&id].display;
+            validate_display(Scope::Display(id), display, 0, max_depth, &mut errors);
+        }
+        if let Some(display) = default_display {
+            validate_display(Scope::DefaultDisplay, display, 0, max_depth, &mut errors);
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+fn validate_display(
+    scope: Scope,
+    display: &Display,
+    depth: usize,
+    max_depth: usize,
+    errors: &mut Vec<ConfigError>,
+) {
+    let error = |message: String| ConfigError { scope, message };
+
+    // Checked before descending, so a runaway tree is reported once rather than per level
+    if depth > max_depth {
+        errors.push(error(format!(
+            "displays are nested more than {} levels deep",
+            max_depth
+        )));
+        return;
+    }
+
+    match display {
+        Display::Webpage { url, .. } => {
+            if let Some(problem) = url_problem(url) {
+                errors.push(error(format!("webpage URL '{}' {}", url, problem)));
+            }
+        }
+        Display::Command { exec, .. } if exec.trim().is_empty() => {
+            errors.push(error("command has no program to run".to_string()))
+        }
+        Display::Slideshow { urls, .. } => {
+            if urls.is_empty() {
+                errors.push(error("slideshow has no URLs".to_string()));
+            }
+            for url in urls {
+                if let Some(problem) = url_problem(url) {
+                    errors.push(error(format!("slideshow URL '{}' {}", url, problem)));
+                }
+            }
+        }
+        Display::Clock { format, .. }
+            if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) =>
+        {
+            errors.push(error(format!("clock format '{}' is invalid", format)))
+        }
+        Display::Text {
+            size: Some(size), ..
+        } if !(size.is_finite() && *size > 0.0 && *size <= text::MAX_SIZE) => {
+            errors.push(error(format!(
+                "text size {} must be positive and at most {}",
+                size,
+                text::MAX_SIZE
+            )))
+        }
+        Display::Split { items, .. } | Display::Stack { items, .. } => {
+            if let Display::Split { sizes, .. } = display
+                && sizes.iter().any(|size| match size {
+                    SplitSize::Percent(percent) => percent.is_nan() || *percent < 0.0,
+                    SplitSize::Pixels(pixels) => *pixels < 0,
+                    SplitSize::Fill => false,
+                })
+            {
+                errors.push(error("split has an invalid size".to_string()));
+            }
+            if items.is_empty() {
+                errors.push(error(format!(
+                    "{} has no items",
+                    display.kind().to_lowercase()
+                )));
+            }
+            for item in items {
+                validate_display(scope, item, depth + 1, max_depth, errors);
+            }
+        }
+        Display::Schedule { entries } => {
+            if entries.is_empty() {
+                errors.push(error("schedule has no entries".to_string()));
+            }
+            for entry in entries {
+                validate_display(scope, &entry.display, depth + 1, max_depth, errors);
+            }
+        }
+        Display::Absolute {
+            width,
+            height,
+            child,
+            ..
+        } => {
+            if *width <= 0 || *height <= 0 {
+                errors.push(error(format!(
+                    "absolute rectangle is {}x{}, it needs a positive size",
+                    width, height
+                )));
+            }
+            validate_display(scope, child, depth + 1, max_depth, errors);
+        }
+        Display::Grid {
+            rows, cols, items, ..
+        } => {
+            if *rows == 0 || *cols == 0 {
+                errors.push(error(format!(
+                    "grid is {}x{}, it needs at least one cell",
+                    rows, cols
+                )));
+            }
+            let cells = (*rows as usize) * (*cols as usize);
+            if items.len() > cells {
+                errors.push(error(format!(
+                    "grid has {} items but only {} cells",
+                    items.len(),
+                    cells
+                )));
+            }
+            for item in items {
+                validate_display(scope, item, depth + 1, max_depth, errors);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// What's wrong with `url`, if anything. Any scheme is allowed, as browsers open
+/// `file:` and `about:` pages too, but web URLs need a host.
+fn url_problem(url: &str) -> Option<&'static str> {
+    if url.trim().is_empty() {
+        return Some("is empty");
+    }
+    if url.chars().any(char::is_whitespace) {
+        return Some("contains whitespace");
+    }
+
+    let Some((scheme, rest)) = url.split_once(':') else {
+        return Some("has no scheme, such as https://");
+    };
+    let valid_scheme = scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'));
+    if !valid_scheme {
+        return Some("has no scheme, such as https://");
+    }
+
+    if matches!(scheme.to_ascii_lowercase().as_str(), "http" | "https") {
+        let host = rest
+            .strip_prefix("//")
+            .map(|rest| rest.split(['/', '?', '#']).next().unwrap_or_default());
+        if host.is_none_or(|host| host.is_empty() || host.starts_with(':')) {
+            return Some("has no host");
+        }
+    }
+    None
+}
+
+/// Loads and validates the config at `path` without starting anything, reporting every
+/// problem found. Returns the exit code: 0 if the config is fine, 1 otherwise.
+pub fn check(path: &Path, format: CheckFormat) -> i32 {
+    // An error of its own means the file couldn't be loaded at all
+    let problems: Result<Vec<ConfigError>, String> = match load(path) {
+        Ok(config) => Ok(config.validate().err().unwrap_or_default()),
+        Err(err) => Err(err.to_string()),
+    };
+    let ok = problems.as_ref().is_ok_and(|errors| errors.is_empty());
+
+    match format {
+        CheckFormat::Text if ok => println!("{}: ok", path.display()),
+        CheckFormat::Text => match &problems {
+            Ok(errors) => {
+                for err in errors {
+                    eprintln!("{}: {}", path.display(), err);
+                }
+            }
+            Err(message) => eprintln!("{}: {}", path.display(), message),
+        },
+        CheckFormat::Json => {
+            // The default display is named `default`, and a file that didn't load has none
+            let errors: Vec<serde_json::Value> = match &problems {
+                Ok(errors) => errors
+                    .iter()
+                    .map(|err| match err.scope {
+                        Scope::Display(id) => {
+                            serde_json::json!({ "display": id, "message": err.message })
+                        }
+                        Scope::DefaultDisplay => {
+                            serde_json::json!({ "display": "default", "message": err.message })
+                        }
+                        Scope::Setting(name) => {
+                            serde_json::json!({ "setting": name, "message": err.message })
+                        }
+                    })
+                    .collect(),
+                Err(message) => vec![serde_json::json!({ "display": null, "message": message })],
+            };
+            let report = serde_json::json!({
+                "path": path,
+                "ok": ok,
+                "errors": errors,
+            });
+            println!("{}", report);
+        }
+    }
+
+    if ok { 0 } else { 1 }
+}
+
+/// Picks the config file from, in order: the `--config` argument, the `SCREENS_CONFIG`
+/// environment variable, `$XDG_CONFIG_HOME/screens/config.json` if it exists, and finally
+/// `config.json` in the working directory.
+/// The result is made absolute so log messages show exactly which file was used.
+pub fn resolve_path(cli: Option<PathBuf>) -> PathBuf {
+    let path = cli
+        .or_else(|| env::var_os("SCREENS_CONFIG").map(PathBuf::from))
+        .or_else(|| xdg_config_path().filter(|path| path.exists()))
+        .unwrap_or_else(|| PathBuf::from("config.json"));
+
+    std::path::absolute(&path).unwrap_or(path)
+}
+
+/// `$XDG_CONFIG_HOME/screens/config.json`, with `XDG_CONFIG_HOME` defaulting to
+/// `~/.config` as the base directory spec asks.
+fn xdg_config_path() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+
+    Some(base.join("screens").join("config.json"))
+}
+
+/// Reads the config at `path`, choosing the format from its extension.
+/// Anything other than `.toml`, `.yaml` or `.yml` is treated as JSON.
+///
+/// Files that are only a map of display ids, as written before the `displays` section
+/// existed, are still accepted and get the default browser settings.
+#[instrument(skip(path), fields(path = %path.display()))]
+pub fn load(path: &Path) -> Result<Config, LoadError> {
+    let mut config = read(path)?;
+    expand_env(&mut config.displays, config.default_display.as_mut())?;
+    info!(displays = config.displays.len(), "Loaded config");
+
+    Ok(config)
+}
+
+/// Parses the config at `path` as written, leaving `${VAR}` patterns unexpanded.
+fn read(path: &Path) -> Result<Config, LoadError> {
+    let content = fs::read_to_string(path).map_err(LoadError::Io)?;
+
+    let config: Config = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("toml") => {
+            let shape: Shape = toml::from_str(&content).map_err(LoadError::Toml)?;
+            if shape.displays.is_some() {
+                toml::from_str(&content).map_err(LoadError::Toml)?
+            } else {
+                Config {
+                    displays: toml::from_str::<Displays>(&content)
+                        .map_err(LoadError::Toml)?
+                        .0,
+                    ..Config::default()
+                }
+            }
+        }
+        Some("yaml" | "yml") => {
+            // Anchors and aliases resolve while parsing, `<<` merge keys need a second pass
+            let mut value: serde_yaml::Value =
+                serde_yaml::from_str(&content).map_err(LoadError::Yaml)?;
+            value.apply_merge().map_err(LoadError::Yaml)?;
+            check_yaml_ids(&value).map_err(LoadError::Yaml)?;
+
+            // serde_yaml only accepts `!Tag` enums and won't read quoted ids as numbers.
+            // Going through JSON lets YAML use the same `Webpage: {...}` maps as the
+            // other formats.
+            let value = serde_json::to_value(value).map_err(LoadError::Json)?;
+            let shape: Shape = serde_json::from_value(value.clone()).map_err(LoadError::Json)?;
+            if shape.displays.is_some() {
+                serde_json::from_value(value).map_err(LoadError::Json)?
+            } else {
+                Config {
+                    displays: serde_json::from_value::<Displays>(value)
+                        .map_err(LoadError::Json)?
+                        .0,
+                    ..Config::default()
+                }
+            }
+        }
+        _ => {
+            let shape: Shape = serde_json::from_str(&content).map_err(LoadError::Json)?;
+            if shape.displays.is_some() {
+                serde_json::from_str(&content).map_err(LoadError::Json)?
+            } else {
+                Config {
+                    displays: serde_json::from_str::<Displays>(&content)
+                        .map_err(LoadError::Json)?
+                        .0,
+                    ..Config::default()
+                }
+            }
+        }
+    };
+
+    Ok(config)
+}
+
+/// Fails on a display id written both as a number and as a string, such as `1` and `"1"`.
+/// YAML counts those as different keys, but they'd silently merge going through JSON.
+fn check_yaml_ids(value: &serde_yaml::Value) -> Result<(), serde_yaml::Error> {
+    let displays = value.get("displays").unwrap_or(value);
+    let Some(displays) = displays.as_mapping() else {
+        return Ok(());
+    };
+
+    let mut seen = HashSet::new();
+    for key in displays.keys() {
+        let id = match key {
+            serde_yaml::Value::Number(number) => number.to_string(),
+            serde_yaml::Value::String(string) => string.clone(),
+            _ => continue,
+        };
+        if !seen.insert(id.clone()) {
+            return Err(de::Error::custom(format!(
+                "display {} is defined more than once",
+                id
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Writes `displays` into the config at `path`, keeping its format and other settings.
+/// Displays the file already has are written as they appear there, so `${VAR}` patterns
+/// aren't replaced by their values. The file is replaced by renaming a temporary one over
+/// it, so it's never seen half written. Returns what was written.
+pub fn save(path: &Path, displays: &HashMap<u32, Root>) -> Result<String, String> {
+    let mut config = match read(path) {
+        Ok(config) => config,
+        Err(LoadError::Io(err)) if err.kind() == io::ErrorKind::NotFound => Config::default(),
+        Err(err) => return Err(format!("not overwriting unreadable config: {}", err)),
+    };
+
+    let mut expanded = config.displays.clone();
+    // Unset variables only mean a display won't match, and is written as it's running
+    let _ = expand_env(&mut expanded, None);
+    config.displays = displays
+        .iter()
+        .map(|(id, root)| {
+            let root = match (expanded.get(id), config.displays.get(id)) {
+                (Some(expanded), Some(raw)) if expanded == root => raw.clone(),
+                _ => root.clone(),
+            };
+            (*id, root)
+        })
+        .collect();
+
+    // Going through JSON turns the numeric display ids into the string keys TOML needs
+    let value = serde_json::to_value(&config).map_err(|err| err.to_string())?;
+    let content = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("toml") => toml::to_string_pretty(&value).map_err(|err| err.to_string())?,
+        Some("yaml" | "yml") => serde_yaml::to_string(&value).map_err(|err| err.to_string())?,
+        _ => serde_json::to_string_pretty(&value).map_err(|err| err.to_string())? + "\n",
+    };
+
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temporary = path.with_file_name(format!(".{}.tmp", file_name));
+    fs::write(&temporary, &content).map_err(|err| err.to_string())?;
+    fs::rename(&temporary, path).map_err(|err| {
+        let _ = fs::remove_file(&temporary);
+        err.to_string()
+    })?;
+
+    info!(path = %path.display(), displays = displays.len(), "Saved config");
+    Ok(content)
+}
+
+/// Expands `${VAR}` in webpage and slideshow URLs from the environment, so tokens and
+/// hostnames can stay out of the file. `$$` is a literal `$`. Fails listing every variable
+/// that isn't set.
+fn expand_env(
+    displays: &mut HashMap<u32, Root>,
+    default_display: Option<&mut Display>,
+) -> Result<(), LoadError> {
+    let mut missing = Vec::new();
+    for root in displays.values_mut() {
+        expand_display(&mut root.display, &mut missing);
+    }
+    if let Some(display) = default_display {
+        expand_display(display, &mut missing);
+    }
+
+    missing.sort();
+    missing.dedup();
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(LoadError::Env(missing))
+    }
+}
+
+fn expand_display(display: &mut Display, missing: &mut Vec<String>) {
+    match display {
+        Display::Webpage { url, .. } => *url = expand_vars(url, env_var, missing),
+        Display::Slideshow { urls, .. } => {
+            for url in urls {
+                *url = expand_vars(url, env_var, missing);
+            }
+        }
+        Display::Split { items, .. }
+        | Display::Grid { items, .. }
+        | Display::Stack { items, .. } => {
+            for item in items {
+                expand_display(item, missing);
+            }
+        }
+        Display::Schedule { entries } => {
+            for entry in entries {
+                expand_display(&mut entry.display, missing);
+            }
+        }
+        Display::Absolute { child, .. } => expand_display(child, missing),
+        _ => {}
+    }
+}
+
+fn env_var(name: &str) -> Option<String> {
+    env::var(name).ok()
+}
+
+/// Replaces each `${NAME}` in `text` with what `lookup` gives for it, and `$$` with `$`.
+/// Names `lookup` has nothing for are added to `missing`.
+fn expand_vars(
+    text: &str,
+    lookup: impl Fn(&str) -> Option<String>,
+    missing: &mut Vec<String>,
+) -> String {
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if let Some(after) = rest.strip_prefix("$$") {
+            expanded.push('$');
+            rest = after;
+        } else if let Some((name, after)) = rest
+            .strip_prefix("${")
+            .and_then(|inner| inner.split_once('}'))
+        {
+            match lookup(name) {
+                Some(value) => expanded.push_str(&value),
+                None => missing.push(name.to_string()),
+            }
+            rest = after;
+        } else {
+            // A lone `$` isn't a pattern, so keep it as written
+            expanded.push('$');
+            rest = &rest[1..];
+        }
+    }
+
+    expanded.push_str(rest);
+    expanded
+}
+
+/// Watches the directory containing `path` and has `handle` flag whenever the config file
+/// itself is written or replaced. Editors commonly save by renaming over the original, so
+/// watching the file directly would lose track of it after the first save.
+pub fn watch(path: &Path, handle: &LoopHandle<'static, App>) -> notify::Result<RecommendedWatcher> {
+    let (tx, rx) = channel::channel();
+    handle
+        .insert_source(rx, |event, _, app: &mut App| {
+            if let channel::Event::Msg(()) = event {
+                app.config_changed = true;
+            }
+        })
+        .map_err(|err| notify::Error::io(err.error.into()))?;
+    let file_name = path.file_name().map(|name| name.to_owned());
+
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+        let Ok(event) = res else { return };
+        if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+            return;
+        }
+
+        if event
+            .paths
+            .iter()
+            .any(|changed| changed.file_name() == file_name.as_deref())
+        {
+            let _ = tx.send(());
+        }
+    })?;
+
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    watcher.watch(&directory, RecursiveMode::NonRecursive)?;
+
+    Ok(watcher)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Loads `content` as if it were a config file called `file_name`.
+    fn load_str(file_name: &str, content: &str) -> Result<Config, LoadError> {
+        let path = env::temp_dir().join(format!("screens-{}-{}", std::process::id(), file_name));
+        fs::write(&path, content).unwrap();
+        let config = load(&path);
+        let _ = fs::remove_file(&path);
+        config
+    }
+
+    fn loaded(file_name: &str, content: &str) -> Config {
+        load_str(file_name, content).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    #[test]
+    fn json_and_toml_load_the_same_displays() {
+        let json = loaded(
+            "same.json",
+            r#"{
+                "displays": {
+                    "1": { "Webpage": { "url": "https://example.com" } },
+                    "2": {
+                        "output": "HDMI-A-1",
+                        "Split": {
+                            "vertical": true,
+                            "items": [
+                                { "Image": { "path": "/srv/logo.png" } },
+                                { "Clock": { "format": "%H:%M" } }
+                            ]
+                        }
+                    }
+                }
+            }"#,
+        );
+        let toml = loaded(
+            "same.toml",
+            r#"
+                [displays.1.Webpage]
+                url = "https://example.com"
+
+                [displays.2]
+                output = "HDMI-A-1"
+
+                [displays.2.Split]
+                vertical = true
+                items = [
+                    { Image = { path = "/srv/logo.png" } },
+                    { Clock = { format = "%H:%M" } },
+                ]
+            "#,
+        );
+
+        assert_eq!(json.displays.len(), 2);
+        assert!(json.displays == toml.displays);
+    }
+
+    #[test]
+    fn yaml_anchors_and_merge_keys_round_trip() {
+        let yaml = loaded(
+            "anchors.yaml",
+            r#"
+                common: &page
+                  browser: chromium
+                  url: https://example.com
+                displays:
+                  1:
+                    Webpage: *page
+                  2:
+                    Webpage:
+                      <<: *page
+                      url: https://example.com/other
+            "#,
+        );
+        let json = loaded(
+            "anchors.json",
+            r#"{
+                "displays": {
+                    "1": { "Webpage": { "browser": "chromium", "url": "https://example.com" } },
+                    "2": {
+                        "Webpage": { "browser": "chromium", "url": "https://example.com/other" }
+                    }
+                }
+            }"#,
+        );
+        assert!(yaml.displays == json.displays);
+
+        let path = env::temp_dir().join(format!("screens-{}-saved.yaml", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let saved =
+            save(&path, &yaml.displays).and_then(|_| load(&path).map_err(|err| err.to_string()));
+        let _ = fs::remove_file(&path);
+        let saved = saved.unwrap_or_else(|err| panic!("{}", err));
+        assert!(saved.displays == yaml.displays);
+    }
+
+    #[test]
+    fn repeated_display_ids_are_rejected() {
+        let json = r#"{ "displays": {
+            "1": { "Webpage": { "url": "https://a.example.com" } },
+            "1": { "Webpage": { "url": "https://b.example.com" } }
+        } }"#;
+        let legacy = r#"{
+            "1": { "Webpage": { "url": "https://a.example.com" } },
+            "01": { "Webpage": { "url": "https://b.example.com" } }
+        }"#;
+        let yaml = r#"
+            displays:
+              1: { Webpage: { url: https://a.example.com } }
+              "1": { Webpage: { url: https://b.example.com } }
+        "#;
+
+        for (file_name, content) in [
+            ("repeated.json", json),
+            ("repeated-legacy.json", legacy),
+            ("repeated.yaml", yaml),
+        ] {
+            match load_str(file_name, content) {
+                Err(err) => assert!(
+                    err.to_string()
+                        .contains("display 1 is defined more than once"),
+                    "{}: {}",
+                    file_name,
+                    err
+                ),
+                Ok(_) => panic!("{}: the repeated id was accepted", file_name),
+            }
+        }
+    }
+
+    #[test]
+    fn urls_need_a_scheme_and_host() {
+        for url in [
+            "https://example.com",
+            "http://localhost:3000/d?orgId=1",
+            "file:///srv/page.html",
+            "about:blank",
+        ] {
+            assert_eq!(url_problem(url), None, "{}", url);
+        }
+        for url in [
+            "",
+            "example.com",
+            "://example.com",
+            "https://",
+            "https:/x",
+            "https://a b",
+        ] {
+            assert!(url_problem(url).is_some(), "{}", url);
+        }
+    }
+
+    #[test]
+    fn http_beyond_loopback_needs_a_token() {
+        let config = |address: &str, token: Option<&str>| Config {
+            http_address: Some(address.to_string()),
+            http_token: token.map(str::to_string),
+            ..Config::default()
+        };
+
+        assert!(config("127.0.0.1:7878", None).validate().is_ok());
+        assert!(config("[::1]:7878", None).validate().is_ok());
+        assert!(config("0.0.0.0:7878", Some("secret")).validate().is_ok());
+        assert!(config("0.0.0.0:7878", None).validate().is_err());
+        assert!(config("0.0.0.0:7878", Some("")).validate().is_err());
+        assert!(config("not an address", None).validate().is_err());
+    }
+
+    #[test]
+    fn expand_vars_substitutes_variables() {
+        let vars = HashMap::from([("HOST", "dash.example.com")]);
+        let lookup = |name: &str| vars.get(name).map(|value| value.to_string());
+        let mut missing = Vec::new();
+
+        assert_eq!(
+            expand_vars("https://${HOST}/d", lookup, &mut missing),
+            "https://dash.example.com/d"
+        );
+        assert_eq!(
+            expand_vars("cost: $$5, $x", lookup, &mut missing),
+            "cost: $5, $x"
+        );
+        assert!(missing.is_empty());
+
+        expand_vars("https://${PORTLESS}/", lookup, &mut missing);
+        assert_eq!(missing, ["PORTLESS"]);
+    }
+
+    #[test]
+    fn unset_variables_fail_the_load() {
+        let result = load_str(
+            "unset.json",
+            r#"{
+                "displays": {
+                    "1": { "Slideshow": { "urls": ["https://${SCREENS_TEST_UNSET}/"], "interval_secs": 5 } }
+                },
+                "default_display": { "Webpage": { "url": "https://${SCREENS_TEST_MISSING}/" } }
+            }"#,
+        );
+        match result {
+            Err(LoadError::Env(names)) => {
+                assert_eq!(names, ["SCREENS_TEST_MISSING", "SCREENS_TEST_UNSET"])
+            }
+            _ => panic!("expected the unset variables to be reported"),
+        }
+    }
+}
@@ -0,0 +1,267 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, ErrorKind, Read, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use smithay::reexports::calloop::{Interest, LoopHandle, Mode, PostAction, generic::Generic};
+use tracing::{info, warn};
+
+use crate::{App, PanelId, Root, status::PanelStatus};
+
+/// A command sent as one line of JSON, e.g. `{"set": {"id": 1, "display": {...}}}` or
+/// `{"remove": {"id": 2}}`.
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Request {
+    /// Replaces (or adds) the top-level display `id`
+    Set { id: u32, display: Root },
+    /// Takes the top-level display `id` off screen
+    Remove { id: u32 },
+    /// Gives keyboard focus to panel `id`, such as `"2.1"`, from now on
+    Focus { id: PanelId },
+    /// Re-reads the config file
+    Reload {},
+    /// Saves the next frame of each output to a PNG named after `path` and the output,
+    /// shrunk unless `full_res` is set. Without a path, it's timestamped in the
+    /// screenshot directory.
+    Screenshot {
+        #[serde(default)]
+        path: Option<PathBuf>,
+        #[serde(default)]
+        full_res: bool,
+    },
+    /// Writes the running displays back to the config file
+    Save {},
+    /// Reports every panel's kind, process, window geometry and last restart
+    Status {},
+}
+
+/// The reply written back for every request line.
+#[derive(Serialize)]
+struct Response {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    panels: Option<Vec<PanelStatus>>,
+}
+
+/// A Unix socket accepting line-delimited JSON commands. The listener and every
+/// connection are event loop sources, so commands are handled as soon as they arrive.
+pub struct Server {
+    path: PathBuf,
+}
+
+impl Server {
+    /// Listens on `path`, with `handle` accepting connections as they come in.
+    pub fn bind(path: &Path, handle: &LoopHandle<'static, App>) -> io::Result<Self> {
+        // A socket left behind by a previous run would make bind fail
+        if path.exists() && UnixStream::connect(path).is_err() {
+            fs::remove_file(path)?;
+        }
+
+        let listener = UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+        let source = Generic::new(listener, Interest::READ, Mode::Level);
+        handle
+            .insert_source(source, |_, listener, app: &mut App| {
+                for stream in accept(listener.as_ref()) {
+                    app.watch_connection(stream);
+                }
+                Ok(PostAction::Continue)
+            })
+            .map_err(|err| io::Error::other(err.error))?;
+        info!("Listening for commands on '{}'", path.display());
+
+        Ok(Server {
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Every connection waiting on `listener`.
+fn accept(listener: &UnixListener) -> Vec<UnixStream> {
+    let mut streams = Vec::new();
+    loop {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                if let Err(err) = stream.set_nonblocking(true) {
+                    warn!("Failed to configure control connection: {}", err);
+                    continue;
+                }
+                streams.push(stream);
+            }
+            Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+            Err(err) => {
+                warn!("Failed to accept control connection: {}", err);
+                break;
+            }
+        }
+    }
+    streams
+}
+
+/// Reads everything `stream` has to offer into `buffer`. Returns whether the peer has
+/// stopped sending.
+fn read_available(mut stream: &UnixStream, buffer: &mut Vec<u8>) -> bool {
+    let mut chunk = [0; 4096];
+    loop {
+        match stream.read(&mut chunk) {
+            Ok(0) => return true,
+            Ok(read) => buffer.extend_from_slice(&chunk[..read]),
+            Err(err) if err.kind() == ErrorKind::WouldBlock => return false,
+            Err(err) if err.kind() == ErrorKind::Interrupted => {}
+            Err(_) => return true,
+        }
+    }
+}
+
+/// Takes every complete line out of `buffer`. Once the peer has hung up, a final request
+/// doesn't need its newline.
+fn take_lines(buffer: &mut Vec<u8>, closed: bool) -> Vec<String> {
+    if closed && !buffer.is_empty() && !buffer.ends_with(b"\n") {
+        buffer.push(b'\n');
+    }
+
+    let mut lines = Vec::new();
+    while let Some(end) = buffer.iter().position(|byte| *byte == b'\n') {
+        let line: Vec<u8> = buffer.drain(..=end).collect();
+        let line = String::from_utf8_lossy(&line).trim().to_string();
+        if !line.is_empty() {
+            lines.push(line);
+        }
+    }
+    lines
+}
+
+fn respond(mut stream: &UnixStream, response: &Response) {
+    let mut reply = serde_json::to_vec(response).expect("Responses always serialize");
+    reply.push(b'\n');
+    if let Err(err) = stream.write_all(&reply) {
+        warn!("Failed to reply on control socket: {}", err);
+    }
+}
+
+impl App {
+    /// Answers each request on `stream` as it arrives, until the peer hangs up.
+    fn watch_connection(&mut self, stream: UnixStream) {
+        let mut buffer = Vec::new();
+        let connection = Generic::new(stream, Interest::READ, Mode::Level);
+        let inserted =
+            self.loop_handle
+                .insert_source(connection, move |_, stream, app: &mut App| {
+                    let stream = stream.as_ref();
+                    let closed = read_available(stream, &mut buffer);
+                    for line in take_lines(&mut buffer, closed) {
+                        respond(stream, &app.handle_line(&line));
+                    }
+                    Ok(if closed {
+                        PostAction::Remove
+                    } else {
+                        PostAction::Continue
+                    })
+                });
+        if let Err(err) = inserted {
+            warn!("Failed to watch control connection: {}", err.error);
+        }
+    }
+
+    /// Applies one command received on the control socket.
+    fn handle_line(&mut self, line: &str) -> Response {
+        let result = match serde_json::from_str::<Request>(line) {
+            Ok(Request::Status {}) => Ok(Some(self.status())),
+            Ok(request) => self.handle_request(request).map(|()| None),
+            Err(err) => Err(format!("invalid request: {}", err)),
+        };
+
+        match result {
+            Ok(panels) => Response {
+                ok: true,
+                error: None,
+                panels,
+            },
+            Err(err) => {
+                warn!("Control command failed: {}", err);
+                Response {
+                    ok: false,
+                    error: Some(err),
+                    panels: None,
+                }
+            }
+        }
+    }
+
+    /// Replaces (or adds) top-level display `id`, respawning only what changed. Nothing
+    /// changes if the result wouldn't validate.
+    pub(crate) fn set_display(&mut self, id: u32, root: Root) -> Result<(), String> {
+        let mut displays: HashMap<u32, Root> = self.displays.clone();
+        displays.insert(id, root);
+        self.replace_displays(displays)
+    }
+
+    /// Replaces every top-level display at once, respawning only what changed. Nothing
+    /// changes if `displays` wouldn't validate. With `persist` set, the result is saved
+    /// to the config file too.
+    pub(crate) fn replace_displays(&mut self, displays: HashMap<u32, Root>) -> Result<(), String> {
+        App::validate(&displays, None, self.max_depth)
+            .map_err(|errors| crate::join_errors(&errors))?;
+        self.apply_displays(displays, false);
+
+        // The change has taken effect either way, so a failed save is only logged
+        if self.persist
+            && let Err(err) = self.save_config()
+        {
+            warn!("Failed to save config: {}", err);
+        }
+        Ok(())
+    }
+
+    fn handle_request(&mut self, request: Request) -> Result<(), String> {
+        match request {
+            Request::Set { id, display } => {
+                info!(id, "Setting display from control socket");
+                self.set_display(id, display)
+            }
+            Request::Remove { id } => {
+                if !self.displays.contains_key(&id) {
+                    return Err(format!("no display with id {}", id));
+                }
+
+                info!(id, "Removing display from control socket");
+                let mut displays = self.displays.clone();
+                displays.remove(&id);
+                self.replace_displays(displays)
+            }
+            Request::Focus { id } => {
+                info!(%id, "Focusing panel from control socket");
+                let result = self.focus_display(&id);
+                self.focus = Some(id);
+                result
+            }
+            Request::Reload {} => {
+                info!("Reloading config from control socket");
+                self.reload_config()
+            }
+            Request::Screenshot { path, full_res } => {
+                let path = path.unwrap_or_else(|| self.default_screenshot_path());
+                self.screenshot(path, full_res)
+            }
+            Request::Save {} => {
+                info!("Saving config from control socket");
+                self.save_config()
+            }
+            // Answered in handle_line, as it has more to reply with than success
+            Request::Status {} => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use std::{
+    io,
+    sync::{
+        Mutex,
+        mpsc::{self, Sender},
+    },
+    time::Duration,
+};
+
+use smithay::reexports::calloop::{LoopHandle, channel};
+use tracing::{info, warn};
+use zbus::{blocking, fdo, interface};
+
+use crate::{App, Display, PanelId, Root};
+
+const NAME: &str = "dev.screens.Compositor";
+const PATH: &str = "/dev/screens/Compositor";
+
+/// How long a D-Bus call waits for the main loop to pick it up.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+enum Command {
+    SetWebpage { id: u32, url: String },
+    ListPanels,
+    ReloadConfig,
+}
+
+enum Reply {
+    Done,
+    Panels(Vec<(String, String)>),
+}
+
+/// A method call handed over from the D-Bus thread, answered through `reply`.
+struct Call {
+    command: Command,
+    reply: Sender<Result<Reply, String>>,
+}
+
+/// The object served at [`PATH`]. Method calls arrive on zbus's own thread, so they're
+/// forwarded to the main loop rather than touching the [`App`] directly.
+struct Interface {
+    calls: Mutex<channel::Sender<Call>>,
+}
+
+impl Interface {
+    fn call(&self, command: Command) -> fdo::Result<Reply> {
+        let (reply, response) = mpsc::channel();
+        self.calls
+            .lock()
+            .map_err(|_| fdo::Error::Failed("compositor is shutting down".to_string()))?
+            .send(Call { command, reply })
+            .map_err(|_| fdo::Error::Failed("compositor is shutting down".to_string()))?;
+
+        response
+            .recv_timeout(REPLY_TIMEOUT)
+            .map_err(|_| fdo::Error::Failed("compositor didn't respond".to_string()))?
+            .map_err(fdo::Error::Failed)
+    }
+}
+
+#[interface(name = "dev.screens.Compositor")]
+impl Interface {
+    /// Shows `url` as top-level display `id`, replacing whatever was there.
+    fn set_webpage(&self, id: u32, url: String) -> fdo::Result<()> {
+        self.call(Command::SetWebpage { id, url }).map(|_| ())
+    }
+
+    /// Every panel's id, such as `3` or `3.1.0`, and its display kind.
+    fn list_panels(&self) -> fdo::Result<Vec<(String, String)>> {
+        match self.call(Command::ListPanels)? {
+            Reply::Panels(panels) => Ok(panels),
+            Reply::Done => Ok(Vec::new()),
+        }
+    }
+
+    /// Re-reads the config file.
+    fn reload_config(&self) -> fdo::Result<()> {
+        self.call(Command::ReloadConfig).map(|_| ())
+    }
+}
+
+/// The compositor's presence on the session bus, when the config's `dbus` asks for it.
+pub struct Server {
+    /// Kept so the name and object stay registered
+    _connection: blocking::Connection,
+}
+
+impl Server {
+    /// Claims our name on the session bus, with `handle` answering method calls as they
+    /// arrive.
+    pub fn connect(handle: &LoopHandle<'static, App>) -> zbus::Result<Self> {
+        let (sender, calls) = channel::channel();
+        handle
+            .insert_source(calls, |event, _, app: &mut App| {
+                if let channel::Event::Msg(call) = event {
+                    app.answer_call(call);
+                }
+            })
+            .map_err(|err| io::Error::from(err.error))?;
+
+        let interface = Interface {
+            calls: Mutex::new(sender),
+        };
+
+        let connection = blocking::connection::Builder::session()?
+            .name(NAME)?
+            .serve_at(PATH, interface)?
+            .build()?;
+        info!("Serving {} on the session bus", NAME);
+
+        Ok(Server {
+            _connection: connection,
+        })
+    }
+}
+
+impl App {
+    fn answer_call(&mut self, call: Call) {
+        let result = self.handle_command(call.command);
+        if let Err(err) = &result {
+            warn!("D-Bus call failed: {}", err);
+        }
+        // The caller may have timed out already
+        let _ = call.reply.send(result);
+    }
+
+    fn handle_command(&mut self, command: Command) -> Result<Reply, String> {
+        match command {
+            Command::SetWebpage { id, url } => {
+                info!(id, url, "Setting webpage from D-Bus");
+                let output = self.displays.get(&id).and_then(|root| root.output.clone());
+                let display = Display::Webpage {
+                    url,
+                    browser: None,
+                    args_template: None,
+                    refresh_secs: None,
+                    profile: None,
+                };
+                self.set_display(id, Root { output, display })
+                    .map(|()| Reply::Done)
+            }
+            Command::ListPanels => {
+                let mut ids: Vec<u32> = self.displays.keys().copied().collect();
+                ids.sort_unstable();
+
+                let mut panels = Vec::new();
+                for id in ids {
+                    list_panels(&PanelId::root(id), &self.displays[&id].display, &mut panels);
+                }
+                Ok(Reply::Panels(panels))
+            }
+            Command::ReloadConfig => {
+                info!("Reloading config from D-Bus");
+                self.reload_config().map(|()| Reply::Done)
+            }
+        }
+    }
+}
+
+fn list_panels(id: &PanelId, display: &Display, panels: &mut Vec<(String, String)>) {
+    panels.push((id.to_string(), display.kind().to_string()));
+    for (index, child) in display.children().iter().enumerate() {
+        list_panels(&id.child(index), child, panels);
+    }
+    if let Display::Schedule { entries } = display {
+        for (index, entry) in entries.iter().enumerate() {
+            list_panels(&id.child(index), &entry.display, panels);
+        }
+    }
+}
@@ -0,0 +1,529 @@
+use std::{
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use fontdue::Font;
+
+use serde::{Deserialize, Serialize};
+use smithay::{
+    backend::{
+        allocator::Fourcc,
+        renderer::{
+            Color32F,
+            element::{
+                Kind,
+                memory::{MemoryRenderBuffer, MemoryRenderBufferRenderElement},
+                solid::{SolidColorBuffer, SolidColorRenderElement},
+            },
+            gles::GlesRenderer,
+        },
+    },
+    utils::{Logical, Point, Rectangle, Scale, Transform},
+};
+
+use tracing::{info, warn};
+
+use crate::{
+    layout,
+    render::PanelElement,
+    text::{self, TextAlign},
+};
+
+/// A color written in config as `#RGB`, `#RRGGBB`, `#RRGGBBAA`, one of the
+/// [`NAMED_COLORS`] such as `black`, or an `[r, g, b, a]` array of floats between 0 and 1.
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "ColorRepr", into = "String")]
+pub struct Color(pub Color32F);
+
+/// CSS color names accepted in place of hex, with the hex digits they stand for.
+const NAMED_COLORS: &[(&str, &str)] = &[
+    ("black", "000000"),
+    ("white", "ffffff"),
+    ("gray", "808080"),
+    ("grey", "808080"),
+    ("silver", "c0c0c0"),
+    ("red", "ff0000"),
+    ("maroon", "800000"),
+    ("orange", "ffa500"),
+    ("yellow", "ffff00"),
+    ("lime", "00ff00"),
+    ("green", "008000"),
+    ("teal", "008080"),
+    ("cyan", "00ffff"),
+    ("blue", "0000ff"),
+    ("navy", "000080"),
+    ("purple", "800080"),
+    ("magenta", "ff00ff"),
+    ("pink", "ffc0cb"),
+    ("transparent", "00000000"),
+];
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ColorRepr {
+    Hex(String),
+    Rgba([f32; 4]),
+}
+
+impl Color {
+    pub const WHITE: Color = Color(Color32F::new(1.0, 1.0, 1.0, 1.0));
+
+    /// Builds a color from straight (not pre-multiplied) components.
+    fn straight(r: f32, g: f32, b: f32, a: f32) -> Self {
+        Color(Color32F::new(r * a, g * a, b * a, a))
+    }
+}
+
+/// Opaque black
+impl Default for Color {
+    fn default() -> Self {
+        Color(Color32F::new(0.0, 0.0, 0.0, 1.0))
+    }
+}
+
+impl TryFrom<ColorRepr> for Color {
+    type Error = String;
+
+    fn try_from(value: ColorRepr) -> Result<Self, Self::Error> {
+        match value {
+            ColorRepr::Hex(hex) => Color::try_from(hex),
+            ColorRepr::Rgba(rgba) => {
+                if rgba.iter().any(|channel| !(0.0..=1.0).contains(channel)) {
+                    return Err(format!(
+                        "invalid color {:?}, channels must be between 0 and 1",
+                        rgba
+                    ));
+                }
+
+                let [r, g, b, a] = rgba;
+                Ok(Color::straight(r, g, b, a))
+            }
+        }
+    }
+}
+
+impl TryFrom<String> for Color {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let invalid = || {
+            format!(
+                "invalid color '{}', expected #RGB, #RRGGBB, #RRGGBBAA or a color name",
+                value
+            )
+        };
+
+        let named = NAMED_COLORS
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(&value))
+            .map(|(_, hex)| *hex);
+        let hex = match named {
+            Some(hex) => hex,
+            None => value.strip_prefix('#').ok_or_else(invalid)?,
+        };
+        if !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+
+        // Each digit of the short form stands for a pair, so `#f80` is `#ff8800`
+        let hex: String = if hex.len() == 3 {
+            hex.chars().flat_map(|digit| [digit, digit]).collect()
+        } else {
+            hex.to_string()
+        };
+        if !matches!(hex.len(), 6 | 8) {
+            return Err(invalid());
+        }
+
+        let mut channels = [255u8; 4];
+        for (channel, index) in channels.iter_mut().zip((0..hex.len()).step_by(2)) {
+            *channel = u8::from_str_radix(&hex[index..index + 2], 16).map_err(|_| invalid())?;
+        }
+
+        let [r, g, b, a] = channels.map(|channel| channel as f32 / 255.0);
+        Ok(Color::straight(r, g, b, a))
+    }
+}
+
+impl From<Color> for String {
+    fn from(color: Color) -> Self {
+        let [r, g, b, a] = color.0.components();
+        let unmultiply = |channel: f32| if a > 0.0 { channel / a } else { 0.0 };
+        let [r, g, b, a] =
+            [unmultiply(r), unmultiply(g), unmultiply(b), a].map(|c| (c * 255.0).round() as u8);
+
+        if a == 255 {
+            format!("#{:02x}{:02x}{:02x}", r, g, b)
+        } else {
+            format!("#{:02x}{:02x}{:02x}{:02x}", r, g, b, a)
+        }
+    }
+}
+
+/// How an image is scaled into its panel.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum ImageFit {
+    /// Scale to fit entirely within the panel, preserving aspect ratio
+    #[default]
+    Contain,
+    /// Scale to fill the whole panel, preserving aspect ratio and cropping the overflow
+    Cover,
+    /// Scale to exactly the panel's size
+    Stretch,
+}
+
+/// Shown in place of images that are missing or fail to decode.
+pub const ERROR_COLOR: Color32F = Color32F::new(0.6, 0.0, 0.0, 1.0);
+
+/// How tall a stack's tab bar is.
+pub const TAB_BAR_HEIGHT: i32 = 4;
+
+/// Segments across `rect`, one per stack child, with the `active` one highlighted.
+pub fn tab_bar(rect: Rectangle<i32, Logical>, count: usize, active: usize) -> Vec<Drawable> {
+    const ACTIVE: Color32F = Color32F::new(0.9, 0.9, 0.9, 0.9);
+    const INACTIVE: Color32F = Color32F::new(0.2, 0.2, 0.2, 0.5);
+
+    layout::divide(rect.size.w, count, &[], 2)
+        .into_iter()
+        .enumerate()
+        .map(|(index, (offset, width))| {
+            let segment = Rectangle::new(
+                rect.loc + Point::from((offset, 0)),
+                (width, rect.size.h).into(),
+            );
+            let color = if index == active { ACTIVE } else { INACTIVE };
+            Drawable::solid(segment, color)
+        })
+        .collect()
+}
+
+/// Content the compositor draws itself instead of handing the panel to a client.
+/// Everything here is drawn into its panel's rectangle on every frame.
+pub enum Drawable {
+    Solid {
+        location: Point<i32, Logical>,
+        buffer: SolidColorBuffer,
+    },
+    Image(Image),
+    /// Boxed, as the parsed font makes it much larger than the others
+    Clock(Box<Clock>),
+    /// Fixed text, rasterized once
+    Label {
+        location: Point<i32, Logical>,
+        buffer: MemoryRenderBuffer,
+    },
+}
+
+impl Drawable {
+    pub fn solid(rect: Rectangle<i32, Logical>, color: Color32F) -> Self {
+        Drawable::Solid {
+            location: rect.loc,
+            buffer: SolidColorBuffer::new(rect.size, color),
+        }
+    }
+
+    /// `text` centered in `rect` on one line, sized to a band across the middle.
+    pub fn label(text: &str, font: &Font, color: Color, rect: Rectangle<i32, Logical>) -> Self {
+        let band = (rect.size.h / 12).clamp(12, 48);
+        let rendered = text::render_fitted(font, text, rect.size.w * 4 / 5, band, color.0);
+        let location = rect.loc
+            + Point::from((
+                (rect.size.w - rendered.width) / 2,
+                (rect.size.h - rendered.height) / 2,
+            ));
+        let buffer = MemoryRenderBuffer::from_slice(
+            &rendered.pixels,
+            Fourcc::Abgr8888,
+            (rendered.width, rendered.height),
+            1,
+            Transform::Normal,
+            None,
+        );
+
+        Drawable::Label { location, buffer }
+    }
+
+    /// `content` wrapped and drawn over `background` across the whole of `rect`, at
+    /// `px` pixels per em. See [`text::render_block`] for what the content may contain.
+    pub fn text(
+        content: &str,
+        font: &Font,
+        px: f32,
+        (color, background): (Color, Color),
+        align: TextAlign,
+        rect: Rectangle<i32, Logical>,
+    ) -> Self {
+        let size = (rect.size.w, rect.size.h);
+        let rendered = text::render_block(font, content, size, px, align, color.0, background.0);
+        let buffer = MemoryRenderBuffer::from_slice(
+            &rendered.pixels,
+            Fourcc::Abgr8888,
+            (rendered.width, rendered.height),
+            1,
+            Transform::Normal,
+            None,
+        );
+
+        Drawable::Label {
+            location: rect.loc,
+            buffer,
+        }
+    }
+
+    /// Builds the render element for this drawable on an output whose top-left corner is
+    /// at `origin`.
+    pub fn render_element(
+        &self,
+        renderer: &mut GlesRenderer,
+        origin: Point<i32, Logical>,
+        scale: Scale<f64>,
+    ) -> Option<PanelElement> {
+        let solid = |location: Point<i32, Logical>, buffer: &SolidColorBuffer| {
+            let location = (location - origin).to_physical_precise_round(scale);
+            SolidColorRenderElement::from_buffer(buffer, location, scale, 1.0, Kind::Unspecified)
+                .into()
+        };
+
+        let mut memory = |location: Point<i32, Logical>, buffer: &MemoryRenderBuffer| {
+            let location = (location - origin).to_f64().to_physical(scale);
+            MemoryRenderBufferRenderElement::from_buffer(
+                renderer,
+                location,
+                buffer,
+                None,
+                None,
+                None,
+                Kind::Unspecified,
+            )
+            .inspect_err(|err| warn!("Failed to upload buffer: {}", err))
+            .ok()
+            .map(PanelElement::from)
+        };
+
+        match self {
+            Drawable::Solid { location, buffer } => Some(solid(*location, buffer)),
+            Drawable::Image(image) => match &image.content {
+                Some((location, buffer)) => memory(*location, buffer),
+                None => Some(solid(image.rect.loc, &image.error)),
+            },
+            Drawable::Clock(clock) => match &clock.content {
+                Some((location, buffer)) => memory(*location, buffer),
+                None => Some(solid(clock.rect.loc, &clock.error)),
+            },
+            Drawable::Label { location, buffer } => memory(*location, buffer),
+        }
+    }
+}
+
+pub struct Image {
+    path: PathBuf,
+    fit: ImageFit,
+    rect: Rectangle<i32, Logical>,
+    modified: Option<SystemTime>,
+    /// The decoded image and where it's drawn, or `None` if it couldn't be loaded
+    content: Option<(Point<i32, Logical>, MemoryRenderBuffer)>,
+    /// Drawn across the panel while the image can't be loaded
+    error: SolidColorBuffer,
+}
+
+impl Image {
+    pub fn new(path: PathBuf, fit: ImageFit, rect: Rectangle<i32, Logical>) -> Self {
+        let mut image = Image {
+            path,
+            fit,
+            rect,
+            modified: None,
+            content: None,
+            error: SolidColorBuffer::new(rect.size, ERROR_COLOR),
+        };
+        image.load();
+        image
+    }
+
+    /// Whether the image decoded, rather than being drawn as [`ERROR_COLOR`].
+    pub fn is_loaded(&self) -> bool {
+        self.content.is_some()
+    }
+
+    /// Reloads the image if the file's modification time has changed since it was last read.
+    pub fn refresh(&mut self) {
+        if modified(&self.path) != self.modified {
+            info!("Image '{}' changed, reloading", self.path.display());
+            self.load();
+        }
+    }
+
+    fn load(&mut self) {
+        self.modified = modified(&self.path);
+        if !self.path.exists() {
+            warn!("Image '{}' not found", self.path.display());
+            self.content = None;
+            return;
+        }
+
+        self.content = match self.decode() {
+            Ok(content) => Some(content),
+            Err(err) => {
+                warn!("Failed to load image '{}': {}", self.path.display(), err);
+                None
+            }
+        };
+    }
+
+    fn decode(&self) -> Result<(Point<i32, Logical>, MemoryRenderBuffer), image::ImageError> {
+        let (width, height) = (
+            self.rect.size.w.max(1) as u32,
+            self.rect.size.h.max(1) as u32,
+        );
+        let filter = image::imageops::FilterType::Triangle;
+
+        let image = image::open(&self.path)?;
+        let image = match self.fit {
+            ImageFit::Contain => image.resize(width, height, filter),
+            ImageFit::Cover => image.resize_to_fill(width, height, filter),
+            ImageFit::Stretch => image.resize_exact(width, height, filter),
+        }
+        .into_rgba8();
+
+        // Contained images are centered, leaving the rest of the panel empty
+        let (width, height) = (image.width() as i32, image.height() as i32);
+        let location = self.rect.loc
+            + Point::from((
+                (self.rect.size.w - width) / 2,
+                (self.rect.size.h - height) / 2,
+            ));
+
+        // RGBA bytes in memory are ABGR8888 in DRM's little-endian notation
+        let buffer = MemoryRenderBuffer::from_slice(
+            image.as_raw(),
+            Fourcc::Abgr8888,
+            (width, height),
+            1,
+            Transform::Normal,
+            None,
+        );
+
+        Ok((location, buffer))
+    }
+}
+
+/// The current time, formatted and drawn by the compositor.
+pub struct Clock {
+    format: String,
+    color: Color32F,
+    rect: Rectangle<i32, Logical>,
+    font: Option<Font>,
+    /// The text currently drawn, so it's only rasterized again once it changes
+    shown: Option<String>,
+    content: Option<(Point<i32, Logical>, MemoryRenderBuffer)>,
+    /// Drawn across the panel when there's no font to draw with
+    error: SolidColorBuffer,
+}
+
+impl Clock {
+    pub fn new(
+        format: String,
+        font: Option<&Path>,
+        color: Color,
+        rect: Rectangle<i32, Logical>,
+    ) -> Self {
+        let font = text::load_font(font)
+            .inspect_err(|err| warn!("Failed to load clock font {}", err))
+            .ok();
+
+        let mut clock = Clock {
+            format,
+            color: color.0,
+            rect,
+            font,
+            shown: None,
+            content: None,
+            error: SolidColorBuffer::new(rect.size, ERROR_COLOR),
+        };
+        clock.refresh();
+        clock
+    }
+
+    /// Redraws the clock if the formatted time has changed since it was last drawn.
+    pub fn refresh(&mut self) {
+        let Some(font) = &self.font else {
+            return;
+        };
+
+        // Formatting fails rather than panicking on specifiers chrono doesn't know
+        let mut now = String::new();
+        if write!(now, "{}", chrono::Local::now().format(&self.format)).is_err() {
+            now = self.format.clone();
+        }
+        if self.shown.as_ref() == Some(&now) {
+            return;
+        }
+
+        let rendered =
+            text::render_fitted(font, &now, self.rect.size.w, self.rect.size.h, self.color);
+        let location = self.rect.loc
+            + Point::from((
+                (self.rect.size.w - rendered.width) / 2,
+                (self.rect.size.h - rendered.height) / 2,
+            ));
+        let buffer = MemoryRenderBuffer::from_slice(
+            &rendered.pixels,
+            Fourcc::Abgr8888,
+            (rendered.width, rendered.height),
+            1,
+            Transform::Normal,
+            None,
+        );
+
+        self.content = Some((location, buffer));
+        self.shown = Some(now);
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses `value` and writes it back out as hex.
+    fn parse(value: &str) -> Result<String, String> {
+        Color::try_from(value.to_string()).map(String::from)
+    }
+
+    #[test]
+    fn colors_parse_from_each_form() {
+        assert_eq!(parse("#f80"), Ok("#ff8800".to_string()));
+        assert_eq!(parse("#FF8800"), Ok("#ff8800".to_string()));
+        assert_eq!(parse("#ff880080"), Ok("#ff880080".to_string()));
+        assert_eq!(parse("orange"), Ok("#ffa500".to_string()));
+        assert_eq!(parse("Grey"), Ok("#808080".to_string()));
+        assert_eq!(parse("transparent"), Ok("#00000000".to_string()));
+
+        let color: Color = serde_json::from_str("[1.0, 0.0, 0.0, 1.0]").unwrap();
+        assert_eq!(String::from(color), "#ff0000");
+        let color: Color = serde_json::from_str(r##""#00f""##).unwrap();
+        assert_eq!(String::from(color), "#0000ff");
+    }
+
+    #[test]
+    fn invalid_colors_are_rejected() {
+        for invalid in [
+            "", "#", "#ff", "#ff88", "#ff8800f", "#gg8800", "ff8800", "blurple",
+        ] {
+            assert_eq!(
+                parse(invalid),
+                Err(format!(
+                    "invalid color '{}', expected #RGB, #RRGGBB, #RRGGBBAA or a color name",
+                    invalid
+                ))
+            );
+        }
+        assert!(serde_json::from_str::<Color>("[2.0, 0.0, 0.0, 1.0]").is_err());
+    }
+}
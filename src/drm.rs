@@ -0,0 +1,445 @@
+use std::{collections::HashMap, os::fd::AsFd, path::Path, time::Duration};
+
+use smithay::{
+    backend::{
+        allocator::{
+            Fourcc,
+            gbm::{GbmAllocator, GbmBufferFlags, GbmDevice},
+        },
+        drm::{DrmDevice, DrmDeviceFd, DrmEvent, GbmBufferedSurface},
+        egl::{EGLContext, EGLDisplay},
+        input::InputEvent,
+        libinput::{LibinputInputBackend, LibinputSessionInterface},
+        renderer::{
+            Bind, ImportDma,
+            damage::{Error as DamageError, OutputDamageTracker},
+            gles::GlesRenderer,
+        },
+        session::{Event as SessionEvent, Session, libseat::LibSeatSession},
+        udev::{self, UdevBackend, UdevEvent},
+    },
+    output::{Mode, Output, PhysicalProperties, Subpixel},
+    reexports::{
+        calloop::{EventLoop, Interest, Mode as Readiness, PostAction, generic::Generic},
+        drm::control::{Device as ControlDevice, ModeTypeFlags, ResourceHandles, connector, crtc},
+        input::Libinput,
+        rustix::fs::OFlags,
+    },
+    utils::DeviceFd,
+};
+use wayland_server::backend::GlobalId;
+
+use tracing::{info, warn};
+
+use crate::App;
+
+type Surface = GbmBufferedSurface<GbmAllocator<DrmDeviceFd>, ()>;
+
+/// A connected monitor being driven by one CRTC.
+struct Screen {
+    connector: connector::Handle,
+    output: Output,
+    global: GlobalId,
+    surface: Surface,
+    damage_tracker: OutputDamageTracker,
+    /// A frame has been queued and we're waiting for its vblank
+    pending_frame: bool,
+}
+
+/// Events collected from the backend's event sources, applied to the [`App`] afterwards.
+#[derive(Default)]
+struct Pending {
+    input: Vec<InputEvent<LibinputInputBackend>>,
+    vblanks: Vec<crtc::Handle>,
+    session_active: Option<bool>,
+    /// Connectors on our GPU may have been plugged or unplugged
+    hotplug: bool,
+}
+
+/// Runs the compositor directly on a TTY through DRM/KMS, with input from libinput.
+pub struct Backend {
+    event_loop: EventLoop<'static, Pending>,
+    pending: Pending,
+    session: LibSeatSession,
+    libinput: Libinput,
+    drm: DrmDevice,
+    gbm: GbmDevice<DrmDeviceFd>,
+    renderer: GlesRenderer,
+    screens: HashMap<crtc::Handle, Screen>,
+}
+
+impl Backend {
+    pub fn new(app: &mut App) -> Self {
+        let event_loop = EventLoop::try_new().expect("Failed to create event loop");
+        let handle = event_loop.handle();
+
+        let (mut session, session_notifier) =
+            LibSeatSession::new().expect("Failed to open a seat session");
+        handle
+            .insert_source(session_notifier, |event, _, pending: &mut Pending| {
+                pending.session_active = Some(matches!(event, SessionEvent::ActivateSession));
+            })
+            .expect("Failed to watch session");
+
+        let mut libinput = Libinput::new_with_udev(LibinputSessionInterface::from(session.clone()));
+        libinput
+            .udev_assign_seat(&session.seat())
+            .expect("Failed to assign libinput to seat");
+        handle
+            .insert_source(
+                LibinputInputBackend::new(libinput.clone()),
+                |event, _, pending: &mut Pending| pending.input.push(event),
+            )
+            .expect("Failed to watch input devices");
+
+        let gpu = udev::primary_gpu(session.seat())
+            .ok()
+            .flatten()
+            .or_else(|| {
+                udev::all_gpus(session.seat())
+                    .ok()
+                    .and_then(|gpus| gpus.into_iter().next())
+            })
+            .expect("No GPU found");
+        info!("Using GPU '{}'", gpu.display());
+
+        let (drm, gbm, renderer) = open_gpu(&mut session, &gpu);
+        let (drm, drm_notifier) = drm;
+        handle
+            .insert_source(
+                drm_notifier,
+                |event, _, pending: &mut Pending| match event {
+                    DrmEvent::VBlank(crtc) => pending.vblanks.push(crtc),
+                    DrmEvent::Error(err) => warn!("DRM error: {}", err),
+                },
+            )
+            .expect("Failed to watch DRM device");
+
+        let device_id = drm.device_id();
+        let udev = UdevBackend::new(session.seat()).expect("Failed to watch udev");
+        handle
+            .insert_source(udev, move |event, _, pending: &mut Pending| {
+                if let UdevEvent::Changed { device_id: changed } = event {
+                    pending.hotplug |= changed == device_id;
+                }
+            })
+            .expect("Failed to watch for hotplug");
+
+        // Our sources live on a loop of their own, so have the main loop wake whenever this
+        // one has events waiting
+        let wakeup = event_loop
+            .as_fd()
+            .try_clone_to_owned()
+            .expect("Failed to watch DRM events");
+        app.loop_handle
+            .insert_source(
+                Generic::new(wakeup, Interest::READ, Readiness::Level),
+                |_, _, _: &mut App| Ok(PostAction::Continue),
+            )
+            .expect("Failed to watch DRM events");
+
+        let mut backend = Backend {
+            event_loop,
+            pending: Pending::default(),
+            session,
+            libinput,
+            drm,
+            gbm,
+            renderer,
+            screens: HashMap::new(),
+        };
+        backend.scan_connectors(app);
+        if backend.screens.is_empty() {
+            warn!("No connected monitors found on '{}'", gpu.display());
+        }
+
+        backend
+    }
+
+    /// Handles pending device events.
+    pub fn dispatch(&mut self, app: &mut App) {
+        if let Err(err) = self
+            .event_loop
+            .dispatch(Some(Duration::ZERO), &mut self.pending)
+        {
+            warn!("Failed to dispatch DRM events: {}", err);
+        }
+
+        match self.pending.session_active.take() {
+            Some(false) => {
+                info!("Session paused");
+                self.libinput.suspend();
+                self.drm.pause();
+            }
+            Some(true) => {
+                info!("Session resumed");
+                if self.libinput.resume().is_err() {
+                    warn!("Failed to resume libinput");
+                }
+                if let Err(err) = self.drm.activate(false) {
+                    warn!("Failed to reactivate DRM device: {}", err);
+                }
+                for screen in self.screens.values_mut() {
+                    screen.surface.reset_buffers();
+                    screen.pending_frame = false;
+                }
+            }
+            None => {}
+        }
+
+        if std::mem::take(&mut self.pending.hotplug) && self.scan_connectors(app) {
+            app.outputs_changed();
+        }
+
+        for event in self.pending.input.drain(..) {
+            app.handle_input(event);
+        }
+
+        for crtc in self.pending.vblanks.drain(..) {
+            let Some(screen) = self.screens.get_mut(&crtc) else {
+                continue;
+            };
+
+            if let Err(err) = screen.surface.frame_submitted() {
+                warn!("Failed to submit frame: {}", err);
+            }
+            screen.pending_frame = false;
+            app.send_frames(&screen.output);
+        }
+    }
+
+    pub fn render(&mut self, app: &mut App) {
+        if !self.session.is_active() {
+            return;
+        }
+
+        for screen in self.screens.values_mut() {
+            if screen.pending_frame {
+                continue;
+            }
+
+            let (mut dmabuf, age) = match screen.surface.next_buffer() {
+                Ok(buffer) => buffer,
+                Err(err) => {
+                    warn!("Failed to get next buffer: {}", err);
+                    continue;
+                }
+            };
+
+            let frame = match self.renderer.bind(&mut dmabuf) {
+                Ok(mut framebuffer) => app.render_frame(
+                    &mut self.renderer,
+                    &mut framebuffer,
+                    &mut screen.damage_tracker,
+                    age as usize,
+                    &screen.output,
+                ),
+                Err(err) => Err(DamageError::Rendering(err)),
+            };
+
+            let frame = match frame {
+                Ok(Some(frame)) => frame,
+                // Nothing changed, so there's no vblank to wait for. The buffer is kept for
+                // next time, and clients hear back right away.
+                Ok(None) => {
+                    app.send_frames(&screen.output);
+                    continue;
+                }
+                Err(err) => {
+                    warn!("Failed to render frame: {}", err);
+                    continue;
+                }
+            };
+
+            match screen
+                .surface
+                .queue_buffer(Some(frame.sync), Some(frame.damage), ())
+            {
+                Ok(()) => screen.pending_frame = true,
+                Err(err) => warn!("Failed to queue frame: {}", err),
+            }
+        }
+
+        app.space.refresh();
+    }
+
+    /// Brings the screens in line with the connectors: outputs are created for newly
+    /// connected monitors, placed to the right of the existing ones and running their
+    /// preferred mode, and removed for unplugged ones. Returns whether anything changed.
+    fn scan_connectors(&mut self, app: &mut App) -> bool {
+        let Ok(resources) = self.drm.resource_handles() else {
+            warn!("Failed to read DRM resources");
+            return false;
+        };
+
+        let connected: Vec<connector::Info> = resources
+            .connectors()
+            .iter()
+            .filter_map(|handle| self.drm.get_connector(*handle, true).ok())
+            .filter(|info| info.state() == connector::State::Connected)
+            .collect();
+
+        let unplugged: Vec<crtc::Handle> = self
+            .screens
+            .iter()
+            .filter(|(_, screen)| {
+                !connected
+                    .iter()
+                    .any(|info| info.handle() == screen.connector)
+            })
+            .map(|(crtc, _)| *crtc)
+            .collect();
+        let mut changed = !unplugged.is_empty();
+        for crtc in unplugged {
+            let Some(screen) = self.screens.remove(&crtc) else {
+                continue;
+            };
+            info!("Disabled output {}", screen.output.name());
+            app.space.unmap_output(&screen.output);
+            app.display_handle.remove_global::<App>(screen.global);
+        }
+
+        for info in connected {
+            if self
+                .screens
+                .values()
+                .any(|screen| screen.connector == info.handle())
+            {
+                continue;
+            }
+
+            changed |= self.add_screen(app, &resources, &info);
+        }
+
+        changed
+    }
+
+    fn add_screen(
+        &mut self,
+        app: &mut App,
+        resources: &ResourceHandles,
+        info: &connector::Info,
+    ) -> bool {
+        let Some(mode) = info
+            .modes()
+            .iter()
+            .find(|mode| mode.mode_type().contains(ModeTypeFlags::PREFERRED))
+            .or_else(|| info.modes().first())
+            .copied()
+        else {
+            return false;
+        };
+
+        let crtc = info
+            .encoders()
+            .iter()
+            .filter_map(|encoder| self.drm.get_encoder(*encoder).ok())
+            .flat_map(|encoder| resources.filter_crtcs(encoder.possible_crtcs()))
+            .find(|crtc| !self.screens.contains_key(crtc));
+        let name = format!("{}-{}", info.interface().as_str(), info.interface_id());
+        let Some(crtc) = crtc else {
+            warn!("No free CRTC for connector {}", name);
+            return false;
+        };
+
+        let surface = match self.drm.create_surface(crtc, mode, &[info.handle()]) {
+            Ok(surface) => surface,
+            Err(err) => {
+                warn!("Failed to create surface for {}: {}", name, err);
+                return false;
+            }
+        };
+        let allocator = GbmAllocator::new(
+            self.gbm.clone(),
+            GbmBufferFlags::RENDERING | GbmBufferFlags::SCANOUT,
+        );
+        let surface = match GbmBufferedSurface::new(
+            surface,
+            allocator,
+            &[Fourcc::Argb8888, Fourcc::Xrgb8888],
+            self.renderer.dmabuf_formats(),
+        ) {
+            Ok(surface) => surface,
+            Err(err) => {
+                warn!("Failed to set up buffers for {}: {}", name, err);
+                return false;
+            }
+        };
+
+        // New monitors go on the right so existing panels keep their coordinates
+        let x = app
+            .space
+            .outputs()
+            .filter_map(|output| app.space.output_geometry(output))
+            .map(|geometry| geometry.loc.x + geometry.size.w)
+            .max()
+            .unwrap_or(0);
+
+        let (width, height) = info.size().unwrap_or((0, 0));
+        let output = Output::new(
+            name.clone(),
+            PhysicalProperties {
+                size: (width as i32, height as i32).into(),
+                subpixel: Subpixel::Unknown,
+                make: "screens".into(),
+                model: name.clone(),
+            },
+        );
+        let output_mode = Mode::from(mode);
+        let global = output.create_global::<App>(&app.display_handle);
+        output.change_current_state(Some(output_mode), None, None, Some((x, 0).into()));
+        output.set_preferred(output_mode);
+        app.configure_output(&output);
+        app.space.map_output(&output, (x, 0));
+        info!(
+            "Enabled output {} at {}x{}, scale {} and {:?}",
+            name,
+            mode.size().0,
+            mode.size().1,
+            output.current_scale().fractional_scale(),
+            output.current_transform()
+        );
+
+        let damage_tracker = OutputDamageTracker::from_output(&output);
+        self.screens.insert(
+            crtc,
+            Screen {
+                connector: info.handle(),
+                output,
+                global,
+                surface,
+                damage_tracker,
+                pending_frame: false,
+            },
+        );
+        true
+    }
+}
+
+fn open_gpu(
+    session: &mut LibSeatSession,
+    path: &Path,
+) -> (
+    (DrmDevice, smithay::backend::drm::DrmDeviceNotifier),
+    GbmDevice<DrmDeviceFd>,
+    GlesRenderer,
+) {
+    let fd = session
+        .open(
+            path,
+            OFlags::RDWR | OFlags::CLOEXEC | OFlags::NOCTTY | OFlags::NONBLOCK,
+        )
+        .expect("Failed to open GPU");
+    let fd = DrmDeviceFd::new(DeviceFd::from(fd));
+
+    let drm = DrmDevice::new(fd.clone(), true).expect("Failed to open DRM device");
+    let gbm = GbmDevice::new(fd).expect("Failed to open GBM device");
+
+    // SAFETY: the GBM device outlives the display, both are owned by the backend
+    let egl = unsafe { EGLDisplay::new(gbm.clone()) }.expect("Failed to create EGL display");
+    let context = EGLContext::new(&egl).expect("Failed to create EGL context");
+    // SAFETY: the context was just created and isn't current on any other thread
+    let renderer = unsafe { GlesRenderer::new(context) }.expect("Failed to create renderer");
+
+    (drm, gbm, renderer)
+}
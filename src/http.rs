@@ -0,0 +1,243 @@
+use std::{collections::HashMap, sync::Arc, thread};
+
+use smithay::reexports::calloop::{LoopHandle, channel};
+use tiny_http::{Header, Method, Request, Response, StatusCode};
+use tracing::{info, warn};
+
+use crate::{App, Display, Root};
+
+/// A small REST API over the top-level displays:
+///
+/// - `GET /displays` returns every display as JSON, keyed by id, as in the config
+/// - `POST /displays` replaces all of them from a JSON body of the same shape
+/// - `GET /panels` returns every display as JSON, keyed by id
+/// - `PUT /panels/{id}` replaces (or adds) a display from a JSON body
+/// - `GET /status` reports every panel's kind, process, window geometry and last restart
+///
+/// With a token, every request has to carry it as `Authorization: Bearer <token>`.
+/// Without one, displays that run a program named in the body are refused, as any local
+/// user could otherwise run whatever they liked as us.
+///
+/// Requests are accepted on a thread of their own and handed to the main loop, so they're
+/// answered as soon as they arrive without touching the [`App`] from another thread.
+pub struct Server {
+    server: Arc<tiny_http::Server>,
+}
+
+impl Server {
+    /// Listens on `address`, with `handle` answering requests as they arrive.
+    pub fn bind(
+        address: &str,
+        token: Option<String>,
+        handle: &LoopHandle<'static, App>,
+    ) -> Result<Self, String> {
+        let server = tiny_http::Server::http(address).map_err(|err| err.to_string())?;
+        let server = Arc::new(server);
+
+        let (sender, requests) = channel::channel();
+        handle
+            .insert_source(requests, move |event, _, app: &mut App| {
+                if let channel::Event::Msg(request) = event {
+                    app.answer_http(request, token.as_deref());
+                }
+            })
+            .map_err(|err| err.error.to_string())?;
+
+        let incoming = Arc::clone(&server);
+        thread::spawn(move || {
+            for request in incoming.incoming_requests() {
+                if sender.send(request).is_err() {
+                    return;
+                }
+            }
+        });
+
+        info!("Serving the HTTP API on {}", address);
+        Ok(Server { server })
+    }
+}
+
+const UNTRUSTED_PROGRAM: &str =
+    "commands, players, browsers and argument templates need `http_token` to be set";
+
+/// Whether `request` carries `token` as its bearer token. Compared in constant time, so
+/// how long a wrong guess takes says nothing about how close it was.
+fn authorized(request: &Request, token: &str) -> bool {
+    let Some(header) = request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Authorization"))
+    else {
+        return false;
+    };
+    let Some(given) = header.value.as_str().strip_prefix("Bearer ") else {
+        return false;
+    };
+
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Whether `display`, or anything nested in it, runs a program named by the request rather
+/// than by the config: a `Command`, or a browser or player override.
+fn names_program(display: &Display) -> bool {
+    let own = match display {
+        Display::Command { .. } => true,
+        Display::Webpage {
+            browser,
+            args_template,
+            ..
+        } => browser.is_some() || args_template.is_some(),
+        Display::Video {
+            player,
+            args_template,
+            ..
+        } => player.is_some() || args_template.is_some(),
+        _ => false,
+    };
+    let scheduled = match display {
+        Display::Schedule { entries } => entries.iter().any(|entry| names_program(&entry.display)),
+        _ => false,
+    };
+
+    own || scheduled || display.children().iter().any(names_program)
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        // Lets the thread waiting on requests finish
+        self.server.unblock();
+    }
+}
+
+impl App {
+    fn answer_http(&mut self, mut request: Request, token: Option<&str>) {
+        let (status, body) = match token {
+            Some(token) if !authorized(&request, token) => {
+                let message = "missing or wrong bearer token";
+                (401, serde_json::json!({ "error": message }).to_string())
+            }
+            _ => self.handle_http(&mut request, token.is_some()),
+        };
+        let json =
+            Header::from_bytes("Content-Type", "application/json").expect("Static header is valid");
+        let response = Response::from_string(body)
+            .with_status_code(StatusCode(status))
+            .with_header(json);
+        if let Err(err) = request.respond(response) {
+            warn!("Failed to reply to HTTP request: {}", err);
+        }
+    }
+
+    /// Answers an authorized request. Unless `trusted`, bodies may not name programs to run.
+    fn handle_http(&mut self, request: &mut Request, trusted: bool) -> (u16, String) {
+        let error = |status, message: String| {
+            let body = serde_json::json!({ "error": message }).to_string();
+            (status, body)
+        };
+
+        let path = request
+            .url()
+            .split('?')
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        let segments: Vec<&str> = path.split('/').filter(|part| !part.is_empty()).collect();
+
+        match (request.method(), segments.as_slice()) {
+            (Method::Get, ["displays" | "panels"]) => match serde_json::to_string(&self.displays) {
+                Ok(body) => (200, body),
+                Err(err) => error(500, err.to_string()),
+            },
+            (Method::Post, ["displays"]) => {
+                let mut body = String::new();
+                if let Err(err) = request.as_reader().read_to_string(&mut body) {
+                    return error(400, err.to_string());
+                }
+                let displays: HashMap<u32, Root> = match serde_json::from_str(&body) {
+                    Ok(displays) => displays,
+                    Err(err) => return error(400, err.to_string()),
+                };
+                if !trusted && displays.values().any(|root| names_program(&root.display)) {
+                    return error(403, UNTRUSTED_PROGRAM.to_string());
+                }
+
+                info!("Replacing displays from HTTP API");
+                match self.replace_displays(displays) {
+                    Ok(()) => (200, "{}".to_string()),
+                    Err(err) => error(422, err),
+                }
+            }
+            (Method::Put, ["panels", id]) => {
+                let Ok(id) = id.parse::<u32>() else {
+                    return error(400, format!("invalid display id '{}'", id));
+                };
+
+                let mut body = String::new();
+                if let Err(err) = request.as_reader().read_to_string(&mut body) {
+                    return error(400, err.to_string());
+                }
+                let root: Root = match serde_json::from_str(&body) {
+                    Ok(root) => root,
+                    Err(err) => return error(400, err.to_string()),
+                };
+                if !trusted && names_program(&root.display) {
+                    return error(403, UNTRUSTED_PROGRAM.to_string());
+                }
+
+                info!(id, "Setting display from HTTP API");
+                match self.set_display(id, root) {
+                    Ok(()) => (200, "{}".to_string()),
+                    Err(err) => error(422, err),
+                }
+            }
+            (Method::Get, ["status"]) => match serde_json::to_string(&self.status()) {
+                Ok(body) => (200, body),
+                Err(err) => error(500, err.to_string()),
+            },
+            (_, ["displays"] | ["panels"] | ["panels", _] | ["status"]) => {
+                error(405, "method not allowed".to_string())
+            }
+            _ => error(404, format!("no route for '{}'", path)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn display(json: &str) -> Display {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn programs_named_anywhere_in_a_body_are_caught() {
+        for json in [
+            r#"{ "Command": { "exec": "sh" } }"#,
+            r#"{ "Webpage": { "url": "https://a.example.com", "browser": "sh" } }"#,
+            r#"{ "Video": { "path": "a.mp4", "args_template": ["-c", "id"] } }"#,
+            r#"{ "Split": { "vertical": false, "items": [
+                { "SolidColor": { "color": "red" } },
+                { "Command": { "exec": "sh" } }
+            ] } }"#,
+            r#"{ "Schedule": { "entries": [
+                { "from": "00:00", "to": "23:59", "display": { "Command": { "exec": "sh" } } }
+            ] } }"#,
+        ] {
+            assert!(names_program(&display(json)), "{}", json);
+        }
+
+        for json in [
+            r#"{ "Webpage": { "url": "https://a.example.com" } }"#,
+            r#"{ "Video": { "path": "a.mp4", "loop_playback": true } }"#,
+            r#"{ "Stack": { "interval_secs": 5, "items": [{ "Clock": {} }] } }"#,
+        ] {
+            assert!(!names_program(&display(json)), "{}", json);
+        }
+    }
+}
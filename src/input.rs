@@ -0,0 +1,348 @@
+use std::{str::FromStr, sync::atomic::Ordering, time::Instant};
+
+use smithay::{
+    backend::input::{
+        AbsolutePositionEvent, Axis, ButtonState, Event, InputBackend, InputEvent, KeyState,
+        KeyboardKeyEvent, PointerAxisEvent, PointerButtonEvent, PointerMotionEvent,
+    },
+    desktop::WindowSurfaceType,
+    input::{
+        keyboard::{FilterResult, Keysym, ModifiersState, xkb},
+        pointer::{AxisFrame, ButtonEvent, MotionEvent},
+    },
+    reexports::wayland_server::protocol::wl_surface::WlSurface,
+    utils::{Logical, Point, SERIAL_COUNTER},
+    wayland::seat::WaylandFocus,
+};
+
+use tracing::{info, warn};
+
+use crate::{App, PanelId};
+
+/// A key chord such as `Ctrl+Alt+R`.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Shortcut {
+    ctrl: bool,
+    alt: bool,
+    shift: bool,
+    logo: bool,
+    key: Keysym,
+}
+
+impl Shortcut {
+    pub fn matches(&self, modifiers: &ModifiersState, key: Keysym) -> bool {
+        self.ctrl == modifiers.ctrl
+            && self.alt == modifiers.alt
+            && self.shift == modifiers.shift
+            && self.logo == modifiers.logo
+            && self.key == key
+    }
+}
+
+impl FromStr for Shortcut {
+    type Err = String;
+
+    fn from_str(chord: &str) -> Result<Self, Self::Err> {
+        let (mut ctrl, mut alt, mut shift, mut logo) = (false, false, false, false);
+        let mut key = None;
+
+        for part in chord.split('+').map(str::trim) {
+            match part.to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => ctrl = true,
+                "alt" => alt = true,
+                "shift" => shift = true,
+                "super" | "logo" | "meta" => logo = true,
+                _ if key.is_some() => return Err(format!("'{}' has more than one key", chord)),
+                _ => {
+                    let sym = xkb::keysym_from_name(part, xkb::KEYSYM_CASE_INSENSITIVE);
+                    if sym.raw() == xkb::keysyms::KEY_NoSymbol {
+                        return Err(format!("unknown key '{}' in '{}'", part, chord));
+                    }
+                    key = Some(sym);
+                }
+            }
+        }
+
+        let key = key.ok_or_else(|| format!("'{}' has no key", chord))?;
+        Ok(Shortcut {
+            ctrl,
+            alt,
+            shift,
+            logo,
+            key,
+        })
+    }
+}
+
+/// Compositor actions bound to shortcuts. These are never forwarded to clients.
+#[derive(Clone, Copy)]
+enum KeyAction {
+    Reload,
+    Quit,
+    Next,
+    Refresh,
+    /// Toggles locked mode, where no input reaches clients
+    Lock,
+    Screenshot,
+    /// The release of a key whose press was intercepted
+    Swallow,
+}
+
+impl App {
+    /// Gives keyboard focus to the first window belonging to panel `id` or one beneath it.
+    pub(crate) fn focus_display(&mut self, id: &PanelId) -> Result<(), String> {
+        let surface = self
+            .space
+            .elements()
+            .filter_map(|window| window.wl_surface())
+            .find(|surface| {
+                self.panel_for_surface(surface)
+                    .is_some_and(|panel| panel.is_within(id))
+            })
+            .map(|surface| surface.into_owned())
+            .ok_or_else(|| format!("panel {} has no window to focus", id))?;
+
+        let keyboard = self
+            .seat
+            .get_keyboard()
+            .ok_or_else(|| "the seat has no keyboard".to_string())?;
+        keyboard.set_focus(self, Some(surface), SERIAL_COUNTER.next_serial());
+        Ok(())
+    }
+
+    /// The panel owning the focused window, or the configured focus if nothing has it.
+    fn focused_panel(&self) -> Option<PanelId> {
+        self.seat
+            .get_keyboard()
+            .and_then(|keyboard| keyboard.current_focus())
+            .and_then(|surface| self.panel_for_surface(&surface))
+            .or_else(|| self.focus.clone())
+    }
+
+    /// Moves the slideshow or stack holding the focused panel on to its next item.
+    fn advance_focused(&mut self) {
+        let mut panel = self.focused_panel();
+        while let Some(id) = panel {
+            if self.slideshows.contains_key(&id) {
+                info!(%id, "Next shortcut pressed, advancing slideshow");
+                self.advance_slideshow(&id, Instant::now());
+                return;
+            }
+            if self.stacks.contains_key(&id) {
+                info!(%id, "Next shortcut pressed, advancing stack");
+                self.advance_stack(&id, Instant::now());
+                return;
+            }
+            panel = id.parent();
+        }
+        info!("Next shortcut pressed, but no slideshow or stack is focused");
+    }
+
+    /// Relaunches the focused panel's process.
+    fn refresh_focused(&mut self) {
+        match self.focused_panel() {
+            Some(id) if self.refresh_panel(&id) => {}
+            _ => info!("Refresh shortcut pressed, but no running panel is focused"),
+        }
+    }
+
+    pub fn handle_input<B: InputBackend>(&mut self, event: InputEvent<B>) {
+        match event {
+            InputEvent::Keyboard { event } => self.handle_key::<B>(event),
+            // Locked mode keeps the pointer away from clients too
+            _ if self.locked => {}
+            InputEvent::PointerMotion { event } => {
+                let location = self.pointer_location + event.delta();
+                self.pointer_moved(location, Event::time_msec(&event));
+            }
+            InputEvent::PointerMotionAbsolute { event } => {
+                let Some(output) = self.space.outputs().next().cloned() else {
+                    return;
+                };
+                let Some(geometry) = self.space.output_geometry(&output) else {
+                    return;
+                };
+                let location = event.position_transformed(geometry.size) + geometry.loc.to_f64();
+                self.pointer_moved(location, Event::time_msec(&event));
+            }
+            InputEvent::PointerButton { event } => {
+                let Some(pointer) = self.seat.get_pointer() else {
+                    return;
+                };
+                let serial = SERIAL_COUNTER.next_serial();
+                let state = event.state();
+
+                // Clicking a window gives it the keyboard as well
+                if state == ButtonState::Pressed
+                    && let Some((surface, _)) = self.surface_under(self.pointer_location)
+                    && let Some(keyboard) = self.seat.get_keyboard()
+                {
+                    keyboard.set_focus(self, Some(surface), serial);
+                }
+
+                pointer.button(
+                    self,
+                    &ButtonEvent {
+                        serial,
+                        time: Event::time_msec(&event),
+                        button: event.button_code(),
+                        state,
+                    },
+                );
+                pointer.frame(self);
+            }
+            InputEvent::PointerAxis { event } => {
+                let Some(pointer) = self.seat.get_pointer() else {
+                    return;
+                };
+
+                let mut frame = AxisFrame::new(Event::time_msec(&event)).source(event.source());
+                for axis in [Axis::Horizontal, Axis::Vertical] {
+                    if let Some(steps) = event.amount_v120(axis) {
+                        frame = frame
+                            .value(axis, event.amount(axis).unwrap_or(steps * 15.0 / 120.0))
+                            .v120(axis, steps as i32);
+                    } else if let Some(amount) = event.amount(axis) {
+                        frame = frame.value(axis, amount);
+                    }
+                }
+                pointer.axis(self, frame);
+                pointer.frame(self);
+            }
+            _ => {}
+        }
+    }
+
+    /// Moves the pointer to `location`, kept within the outputs, and tells whichever
+    /// surface is now under it.
+    fn pointer_moved(&mut self, location: Point<f64, Logical>, time: u32) {
+        let Some(pointer) = self.seat.get_pointer() else {
+            return;
+        };
+
+        let bounds = self
+            .space
+            .outputs()
+            .filter_map(|output| self.space.output_geometry(output))
+            .reduce(|bounds, geometry| bounds.merge(geometry));
+        self.pointer_location = match bounds {
+            Some(bounds) => {
+                let (left, top) = (bounds.loc.x as f64, bounds.loc.y as f64);
+                let (right, bottom) = (
+                    left + bounds.size.w as f64 - 1.0,
+                    top + bounds.size.h as f64 - 1.0,
+                );
+                (location.x.clamp(left, right), location.y.clamp(top, bottom)).into()
+            }
+            None => location,
+        };
+
+        let under = self.surface_under(self.pointer_location);
+        pointer.motion(
+            self,
+            under,
+            &MotionEvent {
+                location: self.pointer_location,
+                serial: SERIAL_COUNTER.next_serial(),
+                time,
+            },
+        );
+        pointer.frame(self);
+    }
+
+    /// The topmost client surface at `location`, with where that surface sits.
+    fn surface_under(
+        &self,
+        location: Point<f64, Logical>,
+    ) -> Option<(WlSurface, Point<f64, Logical>)> {
+        let (window, window_location) = self.space.element_under(location)?;
+        window
+            .surface_under(location - window_location.to_f64(), WindowSurfaceType::ALL)
+            .map(|(surface, offset)| (surface, (offset + window_location).to_f64()))
+    }
+
+    fn handle_key<B: InputBackend>(&mut self, event: B::KeyboardKeyEvent) {
+        let Some(keyboard) = self.seat.get_keyboard() else {
+            return;
+        };
+
+        let keycode = event.key_code();
+        let state = event.state();
+        let serial = SERIAL_COUNTER.next_serial();
+        let time = Event::time_msec(&event);
+
+        let action = keyboard.input(
+            self,
+            keycode,
+            state,
+            serial,
+            time,
+            |app, modifiers, handle| {
+                if state == KeyState::Released {
+                    return match app.suppressed_keys.iter().position(|key| *key == keycode) {
+                        Some(index) => {
+                            app.suppressed_keys.remove(index);
+                            FilterResult::Intercept(KeyAction::Swallow)
+                        }
+                        None => FilterResult::Forward,
+                    };
+                }
+
+                // Only the lock shortcut itself works while locked
+                let mut shortcuts = vec![(app.lock_shortcut, KeyAction::Lock)];
+                if !app.locked {
+                    shortcuts.extend([
+                        (app.reload_shortcut, KeyAction::Reload),
+                        (app.quit_shortcut, KeyAction::Quit),
+                        (app.next_shortcut, KeyAction::Next),
+                        (app.refresh_shortcut, KeyAction::Refresh),
+                        (app.screenshot_shortcut, KeyAction::Screenshot),
+                    ]);
+                }
+
+                let action = handle.raw_latin_sym_or_raw_current_sym().and_then(|key| {
+                    shortcuts
+                        .into_iter()
+                        .find(|(shortcut, _)| shortcut.matches(modifiers, key))
+                        .map(|(_, action)| action)
+                });
+
+                match action {
+                    Some(action) => {
+                        app.suppressed_keys.push(keycode);
+                        FilterResult::Intercept(action)
+                    }
+                    None if app.locked => {
+                        app.suppressed_keys.push(keycode);
+                        FilterResult::Intercept(KeyAction::Swallow)
+                    }
+                    None => FilterResult::Forward,
+                }
+            },
+        );
+
+        match action {
+            Some(KeyAction::Reload) => {
+                info!("Reload shortcut pressed, reloading config");
+                let _ = self.reload_config();
+            }
+            Some(KeyAction::Quit) => {
+                info!("Quit shortcut pressed, shutting down");
+                self.shutdown.store(true, Ordering::Relaxed);
+            }
+            Some(KeyAction::Lock) => {
+                self.locked = !self.locked;
+                info!(locked = self.locked, "Lock shortcut pressed");
+            }
+            Some(KeyAction::Screenshot) => {
+                let path = self.default_screenshot_path();
+                if let Err(err) = self.screenshot(path, true) {
+                    warn!("Screenshot shortcut pressed, but {}", err);
+                }
+            }
+            Some(KeyAction::Next) => self.advance_focused(),
+            Some(KeyAction::Refresh) => self.refresh_focused(),
+            Some(KeyAction::Swallow) | None => {}
+        }
+    }
+}
@@ -0,0 +1,210 @@
+use serde::{Deserialize, Serialize};
+
+/// How much of a split one child takes up.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+pub enum SplitSize {
+    /// A share of what's left once the `Pixels` children have been placed
+    Percent(f32),
+    /// A fixed size
+    Pixels(i32),
+    /// An equal (or weighted) share of whatever the other children leave
+    #[default]
+    Fill,
+}
+
+/// Shrinks `(x, y, width, height)` by `margin` on every side, never below zero size.
+pub fn inset(rect: (i32, i32, i32, i32), margin: i32) -> (i32, i32, i32, i32) {
+    let (x, y, width, height) = rect;
+    let margin = margin.max(0);
+    (
+        x + margin,
+        y + margin,
+        (width - 2 * margin).max(0),
+        (height - 2 * margin).max(0),
+    )
+}
+
+/// Divides `total` pixels between `count` children in proportion to `weights`, returning
+/// each child's offset from the start and its size. Children without a weight get 1, and
+/// weights that are all zero fall back to an equal split.
+///
+/// `gap` pixels are left between neighbouring children. When the gaps don't fit, the
+/// children are squashed to nothing rather than given negative sizes.
+///
+/// Each boundary is computed from the running weight, and sizes are the distance between
+/// neighbouring boundaries, so the children always tile what's left after the gaps exactly.
+pub fn divide(total: i32, count: usize, weights: &[u32], gap: i32) -> Vec<(i32, i32)> {
+    let mut weights: Vec<i64> = (0..count)
+        .map(|index| weights.get(index).copied().unwrap_or(1) as i64)
+        .collect();
+    if weights.iter().all(|weight| *weight == 0) {
+        weights.fill(1);
+    }
+
+    let sum: i64 = weights.iter().sum();
+    if sum == 0 {
+        return Vec::new();
+    }
+
+    let gap = gap.max(0);
+    let available = (total - gap * (count as i32 - 1)).max(0);
+    let boundary = |running: i64| (available as i64 * running / sum) as i32;
+
+    let mut running = 0;
+    weights
+        .iter()
+        .enumerate()
+        .map(|(index, weight)| {
+            let start = boundary(running);
+            running += weight;
+            (start + gap * index as i32, boundary(running) - start)
+        })
+        .collect()
+}
+
+/// Divides `total` pixels between `count` children sized by `sizes`, returning each
+/// child's offset from the start and its size. Children without a size are `Fill`.
+///
+/// `Pixels` children are placed first, then `Percent` children take their share of the
+/// remainder, and `Fill` children split whatever is left by `weights` as in [`divide`].
+/// Percentages adding up to more than 100 are scaled down to fit.
+pub fn divide_sized(
+    total: i32,
+    count: usize,
+    sizes: &[SplitSize],
+    weights: &[u32],
+    gap: i32,
+) -> Vec<(i32, i32)> {
+    if count == 0 {
+        return Vec::new();
+    }
+
+    let gap = gap.max(0);
+    let size = |index: usize| sizes.get(index).copied().unwrap_or_default();
+    let mut remaining = (total - gap * (count as i32 - 1)).max(0);
+    let mut lengths = vec![0; count];
+
+    for (index, length) in lengths.iter_mut().enumerate() {
+        if let SplitSize::Pixels(pixels) = size(index) {
+            *length = pixels.clamp(0, remaining);
+            remaining -= *length;
+        }
+    }
+
+    let percent_total: f32 = (0..count)
+        .filter_map(|index| match size(index) {
+            SplitSize::Percent(percent) => Some(percent.max(0.0)),
+            _ => None,
+        })
+        .sum();
+    let scale = if percent_total > 100.0 {
+        100.0 / percent_total
+    } else {
+        1.0
+    };
+    let base = remaining as f32;
+    for (index, length) in lengths.iter_mut().enumerate() {
+        if let SplitSize::Percent(percent) = size(index) {
+            *length = ((base * percent.max(0.0) * scale / 100.0).round() as i32).min(remaining);
+            remaining -= *length;
+        }
+    }
+
+    let fills: Vec<usize> = (0..count)
+        .filter(|index| size(*index) == SplitSize::Fill)
+        .collect();
+    let fill_weights: Vec<u32> = fills
+        .iter()
+        .map(|index| weights.get(*index).copied().unwrap_or(1))
+        .collect();
+    for (index, (_, length)) in fills
+        .iter()
+        .zip(divide(remaining, fills.len(), &fill_weights, 0))
+    {
+        lengths[*index] = length;
+    }
+
+    let mut offset = 0;
+    lengths
+        .into_iter()
+        .map(|length| {
+            let start = offset;
+            offset += length + gap;
+            (start, length)
+        })
+        .collect()
+}
+
+/// Lays out a split's `count` children in `rect`, side by side or, if `vertical`, one
+/// above the other. `gap` pixels are left between neighbouring children and between them
+/// and the split's edges, so the spacing is the same everywhere and the children stay
+/// centred across the split. `margin` is left around the outside on top of that. A zero
+/// gap and margin tile `rect` exactly.
+pub fn split_rects(
+    rect: (i32, i32, i32, i32),
+    vertical: bool,
+    count: usize,
+    sizes: &[SplitSize],
+    weights: &[u32],
+    gap: i32,
+    margin: i32,
+) -> Vec<(i32, i32, i32, i32)> {
+    let gap = gap.max(0);
+    let (x, y, width, height) = inset(rect, margin.max(0).saturating_add(gap));
+    let total = if vertical { height } else { width };
+
+    divide_sized(total, count, sizes, weights, gap)
+        .into_iter()
+        .map(|(offset, size)| {
+            if vertical {
+                (x, y + offset, width, size)
+            } else {
+                (x + offset, y, size, height)
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn divide_by_weight() {
+        assert_eq!(divide(800, 2, &[3, 1], 0), vec![(0, 600), (600, 200)]);
+    }
+
+    #[test]
+    fn divide_leaves_gaps() {
+        assert_eq!(divide(800, 2, &[], 20), vec![(0, 390), (410, 390)]);
+    }
+
+    #[test]
+    fn divide_sized_mixes_pixels_percent_and_fill() {
+        let sizes = [
+            SplitSize::Pixels(200),
+            SplitSize::Percent(50.0),
+            SplitSize::Fill,
+        ];
+        assert_eq!(
+            divide_sized(1000, 3, &sizes, &[], 0),
+            vec![(0, 200), (200, 400), (600, 400)]
+        );
+    }
+
+    #[test]
+    fn split_gaps_surround_every_child() {
+        assert_eq!(
+            split_rects((0, 0, 800, 600), false, 2, &[], &[], 0, 0),
+            vec![(0, 0, 400, 600), (400, 0, 400, 600)]
+        );
+        assert_eq!(
+            split_rects((0, 0, 800, 600), false, 2, &[], &[], 20, 0),
+            vec![(20, 20, 370, 560), (410, 20, 370, 560)]
+        );
+        assert_eq!(
+            split_rects((100, 0, 600, 320), true, 3, &[], &[], 10, 5),
+            vec![(115, 15, 570, 90), (115, 115, 570, 90), (115, 215, 570, 90)]
+        );
+    }
+}
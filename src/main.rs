@@ -1,40 +1,620 @@
+use notify::RecommendedWatcher;
 use serde::{Deserialize, Serialize};
-use smithay::output::Output;
+use smithay::output::{Output, Scale};
 use smithay::{
-    delegate_compositor, delegate_data_device, delegate_seat, delegate_shm, delegate_xdg_shell,
+    delegate_compositor, delegate_data_device, delegate_output, delegate_seat, delegate_shm,
+    delegate_xdg_shell,
     desktop::{Space, Window},
-    input::{Seat, SeatState},
-    reexports::wayland_server::{Display as WlDisplay, DisplayHandle},
+    input::{
+        Seat, SeatState,
+        keyboard::{Keycode, XkbConfig},
+    },
+    reexports::{
+        calloop::{EventLoop, Interest, LoopHandle, Mode, PostAction, generic::Generic},
+        wayland_server::{Display as WlDisplay, DisplayHandle},
+    },
+    utils::{IsAlive, Logical, Point, Rectangle, SERIAL_COUNTER},
     wayland::{
         compositor::{CompositorClientState, CompositorState},
+        seat::WaylandFocus,
         selection::data_device::DataDeviceState,
         shell::xdg::XdgShellState,
         shm::ShmState,
+        socket::ListeningSocketSource,
+    },
+};
+use std::{
+    collections::HashMap,
+    env,
+    ffi::OsString,
+    fmt, fs,
+    io::{self, Read},
+    os::{raw::c_int, unix::net::UnixStream},
+    path::{Path, PathBuf},
+    process::Command,
+    str::FromStr,
+    sync::{
+        Arc, OnceLock,
+        atomic::{AtomicBool, Ordering},
     },
+    time::{Duration, Instant, SystemTime},
+};
+use tracing::{error, info, info_span, warn};
+use tracing_subscriber::EnvFilter;
+use wayland_server::{
+    Client, Resource,
+    backend::{ClientData, ClientId, DisconnectReason},
+};
+
+use crate::{
+    config::{BrowserConfig, Config, LoadingConfig, OutputConfig, StrayWindows},
+    draw::{Clock, Color, Drawable, ERROR_COLOR, Image, ImageFit},
+    input::Shortcut,
+    layout::SplitSize,
+    process::{PendingRestart, Process, Stopping},
+    schedule::ScheduleEntry,
+    text::TextAlign,
 };
-use std::{collections::HashMap, fs};
-use wayland_server::backend::{ClientData, ClientId, DisconnectReason};
 
 mod basic;
+mod cli;
+mod config;
+mod control;
+mod dbus;
+mod draw;
+mod drm;
+#[cfg(feature = "http")]
+mod http;
+mod input;
+mod layout;
+mod process;
+mod render;
+mod schedule;
+mod screenshot;
+mod status;
+mod text;
+mod winit;
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
 enum Display {
     Webpage {
         url: String,
+        /// Browser executable, overriding the config's `browser.executable`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        browser: Option<String>,
+        /// Browser arguments, overriding the config's `browser.args_template`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        args_template: Option<Vec<String>>,
+        /// Relaunch the browser this often to recover from leaks and stale pages. Also
+        /// accepted as `reload_secs`.
+        #[serde(
+            default,
+            alias = "reload_secs",
+            skip_serializing_if = "Option::is_none"
+        )]
+        refresh_secs: Option<u64>,
+        /// A profile directory kept between runs, so logins survive restarts. Panels
+        /// without one get a fresh profile of their own that's removed on exit.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        profile: Option<String>,
+    },
+    Image {
+        path: String,
+        #[serde(default)]
+        fit: ImageFit,
+    },
+    SolidColor {
+        color: Color,
+    },
+    /// The current time, drawn by the compositor and scaled to fit the panel
+    Clock {
+        /// A chrono strftime format, such as `%H:%M`
+        #[serde(default = "default_clock_format")]
+        format: String,
+        #[serde(default = "default_clock_color")]
+        color: Color,
+        /// A TTF or OTF file. Defaults to DejaVu Sans from the usual system font paths.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        font: Option<String>,
+    },
+    /// A message drawn by the compositor, wrapped to the panel. Each line break starts a
+    /// new paragraph, and `**bold**` and `*italic*` are supported. Text that doesn't fit
+    /// is cut off at the bottom of the panel.
+    Text {
+        content: String,
+        #[serde(default = "default_clock_color")]
+        color: Color,
+        #[serde(default)]
+        background: Color,
+        #[serde(default)]
+        align: TextAlign,
+        /// Pixels per em, at most 1024 and the panel's height. Defaults to a twelfth of the
+        /// panel's height, within 16 to 96.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        size: Option<f32>,
+        /// A TTF or OTF file. Defaults to DejaVu Sans from the usual system font paths.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        font: Option<String>,
+    },
+    /// Any xdg-shell client. `{x}`, `{y}`, `{width}`, `{height}` and `{class}` in `args`
+    /// are replaced with the panel's, which are also in the `SCREENS_*` environment.
+    Command {
+        #[serde(alias = "program")]
+        exec: String,
+        #[serde(default)]
+        args: Vec<String>,
+    },
+    Slideshow {
+        urls: Vec<String>,
+        interval_secs: u64,
+    },
+    /// A video file played by mpv. Unless it loops, the panel is left blank once it ends.
+    Video {
+        path: String,
+        #[serde(default)]
+        loop_playback: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        player: Option<String>,
+        /// Player arguments, with the same placeholders as `browser.args_template` plus
+        /// `{path}`. The path itself is appended afterwards.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        args_template: Option<Vec<String>>,
     },
     Split {
         vertical: bool,
-        items: Vec<Box<Display>>,
+        items: Vec<Display>,
+        /// How much space each child takes. Children without one fill the rest.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        sizes: Vec<SplitSize>,
+        /// Relative shares of the `Fill` children
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        weights: Vec<u32>,
+        /// Pixels left between neighbouring children and around them, inside the split's
+        /// edges, so every panel is spaced alike
+        #[serde(default)]
+        gap: i32,
+        /// Pixels left around the outside of the split, on top of `gap`
+        #[serde(default)]
+        margin: i32,
+    },
+    /// Children sharing one rectangle, shown one at a time. All of them are kept running
+    /// so switching is instant.
+    Stack {
+        interval_secs: u64,
+        items: Vec<Display>,
+        /// Draw a thin bar along the top showing which child is active
+        #[serde(default)]
+        show_tabs: bool,
     },
+    /// Children shown by time of day, such as a menu in the morning and promotions at
+    /// night. The first entry whose window holds the current time is shown, and the panel
+    /// is left empty while none does. Only the shown child is kept running.
+    Schedule {
+        entries: Vec<ScheduleEntry>,
+    },
+    /// Equal cells filled row by row. Cells past the last item are left empty, and
+    /// more items than `rows * cols` fail validation.
+    Grid {
+        rows: u32,
+        cols: u32,
+        items: Vec<Display>,
+        /// Pixels left between neighbouring cells
+        #[serde(default)]
+        gap: i32,
+        /// Pixels left around the outside of the grid
+        #[serde(default)]
+        margin: i32,
+    },
+    /// Places `child` at a fixed rectangle relative to the output it's on, above the
+    /// panels laid out around it. Useful for overlays such as a clock over a dashboard.
+    Absolute {
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+        child: Box<Display>,
+    },
+}
+
+impl Display {
+    /// The containers' children, all of which are kept running. `Schedule` isn't included,
+    /// as only the entry for the current time runs; see `scheduled_child`.
+    fn children(&self) -> &[Display] {
+        match self {
+            Display::Split { items, .. }
+            | Display::Grid { items, .. }
+            | Display::Stack { items, .. } => items,
+            Display::Absolute { child, .. } => std::slice::from_ref(child),
+            _ => &[],
+        }
+    }
+
+    /// Whether `self` and `other` are containers that would place their children in the
+    /// same rectangles, so the children can be updated in place.
+    fn same_layout(&self, other: &Display) -> bool {
+        match (self, other) {
+            (
+                Display::Split {
+                    vertical,
+                    items,
+                    sizes,
+                    weights,
+                    gap,
+                    margin,
+                },
+                Display::Split {
+                    vertical: other_vertical,
+                    items: other_items,
+                    sizes: other_sizes,
+                    weights: other_weights,
+                    gap: other_gap,
+                    margin: other_margin,
+                },
+            ) => {
+                (vertical, items.len(), sizes, weights, gap, margin)
+                    == (
+                        other_vertical,
+                        other_items.len(),
+                        other_sizes,
+                        other_weights,
+                        other_gap,
+                        other_margin,
+                    )
+            }
+            (
+                Display::Grid {
+                    rows,
+                    cols,
+                    items,
+                    gap,
+                    margin,
+                },
+                Display::Grid {
+                    rows: other_rows,
+                    cols: other_cols,
+                    items: other_items,
+                    gap: other_gap,
+                    margin: other_margin,
+                },
+            ) => {
+                (rows, cols, items.len(), gap, margin)
+                    == (
+                        other_rows,
+                        other_cols,
+                        other_items.len(),
+                        other_gap,
+                        other_margin,
+                    )
+            }
+            (
+                Display::Absolute {
+                    x,
+                    y,
+                    width,
+                    height,
+                    ..
+                },
+                Display::Absolute {
+                    x: other_x,
+                    y: other_y,
+                    width: other_width,
+                    height: other_height,
+                    ..
+                },
+            ) => (x, y, width, height) == (other_x, other_y, other_width, other_height),
+            _ => false,
+        }
+    }
+
+    /// The variant name, used to tag log events.
+    fn kind(&self) -> &'static str {
+        match self {
+            Display::Webpage { .. } => "Webpage",
+            Display::Image { .. } => "Image",
+            Display::Clock { .. } => "Clock",
+            Display::Text { .. } => "Text",
+            Display::SolidColor { .. } => "SolidColor",
+            Display::Command { .. } => "Command",
+            Display::Slideshow { .. } => "Slideshow",
+            Display::Video { .. } => "Video",
+            Display::Split { .. } => "Split",
+            Display::Stack { .. } => "Stack",
+            Display::Schedule { .. } => "Schedule",
+            Display::Grid { .. } => "Grid",
+            Display::Absolute { .. } => "Absolute",
+        }
+    }
+}
+
+/// A top-level entry in the config: a display tree, optionally pinned to an output.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+struct Root {
+    /// The output to lay the display out on, such as `HDMI-A-1` or `DP-2`. Displays without
+    /// one are dealt out round-robin across the connected outputs in id order.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    output: Option<String>,
+    #[serde(flatten)]
+    display: Display,
+}
+
+/// Identifies a panel by its top-level config id and the child index taken at each
+/// container on the way down, so ids stay unique whatever shape the tree has.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Hash, Debug)]
+#[serde(try_from = "String", into = "String")]
+pub struct PanelId {
+    /// The top-level display's config id. `None` for copies of the default display.
+    root: Option<u32>,
+    path: Vec<usize>,
+    /// The output a copy of the panel is on, for mirrored displays and the default
+    /// display. `None` for the configured original.
+    output: Option<String>,
+}
+
+impl PanelId {
+    fn root(id: u32) -> Self {
+        PanelId {
+            root: Some(id),
+            path: Vec::new(),
+            output: None,
+        }
+    }
+
+    /// The copy of top-level display `id` laid out on mirrored output `output`.
+    fn mirrored(id: u32, output: &str) -> Self {
+        PanelId {
+            root: Some(id),
+            path: Vec::new(),
+            output: Some(output.to_string()),
+        }
+    }
+
+    /// The copy of the default display laid out on `output`.
+    fn fallback(output: &str) -> Self {
+        PanelId {
+            root: None,
+            path: Vec::new(),
+            output: Some(output.to_string()),
+        }
+    }
+
+    /// Whether this is `ancestor` or lies somewhere beneath it.
+    fn is_within(&self, ancestor: &PanelId) -> bool {
+        self.root == ancestor.root
+            && self.output == ancestor.output
+            && self.path.starts_with(&ancestor.path)
+    }
+
+    /// The container this panel sits in, or `None` for a top-level display.
+    fn parent(&self) -> Option<Self> {
+        let (_, path) = self.path.split_last()?;
+        Some(PanelId {
+            root: self.root,
+            path: path.to_vec(),
+            output: self.output.clone(),
+        })
+    }
+
+    fn child(&self, index: usize) -> Self {
+        let mut path = self.path.clone();
+        path.push(index);
+        PanelId {
+            root: self.root,
+            path,
+            output: self.output.clone(),
+        }
+    }
+
+    /// The window class, or Wayland app id, handed to this panel's client as `{class}`.
+    fn app_id(&self) -> String {
+        format!("{}{}", APP_ID_PREFIX, self)
+    }
+
+    /// The panel an app id made by [`PanelId::app_id`] names. `None` for anything else.
+    fn from_app_id(app_id: &str) -> Option<Self> {
+        app_id.strip_prefix(APP_ID_PREFIX)?.parse().ok()
+    }
 }
 
+/// Prefixed to panel ids to make app ids, such as `screens_panel_3.1`.
+const APP_ID_PREFIX: &str = "screens_panel_";
+
+/// Formats as the root id followed by the child path, e.g. `3` or `3.1.0`, and then the
+/// output for copies, e.g. `3.1.0@HDMI-A-2`. The default display's root is `default`.
+impl fmt::Display for PanelId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.root {
+            Some(root) => write!(f, "{}", root)?,
+            None => write!(f, "default")?,
+        }
+        for index in &self.path {
+            write!(f, ".{}", index)?;
+        }
+        if let Some(output) = &self.output {
+            write!(f, "@{}", output)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for PanelId {
+    type Err = String;
+
+    fn from_str(id: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid panel id '{}', expected e.g. 3 or 3.1.0", id);
+        let (id, output) = match id.split_once('@') {
+            Some((id, output)) if !output.is_empty() => (id, Some(output.to_string())),
+            Some(_) => return Err(invalid()),
+            None => (id, None),
+        };
+        let mut parts = id.split('.');
+        let root = match parts.next() {
+            // Only copies on an output can belong to the default display
+            Some("default") if output.is_some() => None,
+            Some(root) => Some(root.parse().map_err(|_| invalid())?),
+            None => return Err(invalid()),
+        };
+        let path = parts
+            .map(|index| index.parse().map_err(|_| invalid()))
+            .collect::<Result<_, _>>()?;
+
+        Ok(PanelId { root, path, output })
+    }
+}
+
+impl TryFrom<String> for PanelId {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<PanelId> for String {
+    fn from(id: PanelId) -> Self {
+        id.to_string()
+    }
+}
+
+struct Refresh {
+    interval: Duration,
+    next_at: Instant,
+}
+
+struct Slideshow {
+    urls: Vec<String>,
+    index: usize,
+    interval: Duration,
+    next_at: Instant,
+    window_info: (i32, i32, i32, i32),
+}
+
+/// A stack showing one child at a time. Every child is spawned, but the windows and
+/// drawables of inactive ones are kept out of sight.
+struct Stack {
+    items: Vec<Display>,
+    index: usize,
+    interval: Duration,
+    next_at: Instant,
+    window_info: (i32, i32, i32, i32),
+    show_tabs: bool,
+}
+
+/// The shortest time between two frames.
+const FRAME_INTERVAL: Duration = Duration::from_millis(16);
+
+/// Default `Video` player arguments, for mpv.
+const VIDEO_ARGS: [&str; 5] = [
+    "--no-osc",
+    "--no-input-default-bindings",
+    "--no-border",
+    "--wayland-app-id={class}",
+    "--geometry={width}x{height}+{x}+{y}",
+];
+
 pub struct App {
     display_handle: DisplayHandle,
+    /// For registering sources, such as control connections, once running
+    loop_handle: LoopHandle<'static, App>,
+    /// The Wayland socket clients connect to, such as `wayland-1`
+    socket_name: OsString,
     space: Space<Window>,
     compositor_state: CompositorState,
     xdg_shell_state: XdgShellState,
     seat_state: SeatState<App>,
-    displays: HashMap<u32, Display>,
+    displays: HashMap<u32, Root>,
+    /// Where each top-level display was last laid out
+    root_rects: HashMap<u32, (i32, i32, i32, i32)>,
+    /// Laid out on outputs without a display of their own
+    default_display: Option<Display>,
+    /// Where the default display was laid out, by output name
+    default_rects: HashMap<String, (i32, i32, i32, i32)>,
+    /// Outputs showing the first one's displays
+    mirror: Vec<String>,
+    /// Where each mirrored copy of a top-level display was laid out, by the copy's id
+    mirror_rects: HashMap<PanelId, (i32, i32, i32, i32)>,
+    browser: BrowserConfig,
+    background: Color,
+    /// Scale and rotation by output name
+    output_configs: HashMap<String, OutputConfig>,
+    wallpaper: Option<PathBuf>,
+    /// `wallpaper` scaled to each output, by output name
+    wallpapers: HashMap<String, Drawable>,
+    loading: LoadingConfig,
+    /// Shown on webpage panels until their browser's first frame, and on panels whose
+    /// process failed to start. Label above background.
+    placeholders: HashMap<PanelId, Vec<Drawable>>,
+    config_path: PathBuf,
+    /// Kept so the config file stays watched
+    _config_watcher: Option<RecommendedWatcher>,
+    /// Set when the watcher sees the config file change
+    config_changed: bool,
+    /// Save displays changed at runtime back to the config file
+    persist: bool,
+    /// What we last wrote to the config file, so the watcher noticing our own write
+    /// doesn't trigger a reload
+    saved_config: Option<String>,
+    /// Set by SIGHUP to request a config reload
+    hangup: Arc<AtomicBool>,
+    /// Kept so the control socket stays open, and is removed once we exit
+    _control: Option<control::Server>,
+    /// Kept so we stay registered on the session bus
+    _dbus: Option<dbus::Server>,
+    /// Kept so the HTTP API stays up
+    #[cfg(feature = "http")]
+    _http: Option<http::Server>,
+    drawables: HashMap<PanelId, Drawable>,
+    children: HashMap<PanelId, Process>,
+    /// Set by SIGCHLD, so children are only polled once one of them has exited
+    child_exited: Arc<AtomicBool>,
+    pending_restarts: HashMap<PanelId, PendingRestart>,
+    /// Children sent SIGTERM that haven't exited yet. Their panels aren't started again
+    /// until they have, so a browser profile is never in use twice.
+    stopping: HashMap<PanelId, Stopping>,
+    restart_backoff: Duration,
+    max_restarts: Option<u32>,
+    /// How many levels containers may nest before validation fails
+    max_depth: usize,
+    /// No more children are spawned once this many are running or due a restart
+    max_windows: usize,
+    refreshes: HashMap<PanelId, Refresh>,
+    slideshows: HashMap<PanelId, Slideshow>,
+    stacks: HashMap<PanelId, Stack>,
+    schedules: HashMap<PanelId, schedule::Schedule>,
+    /// Indicators for stacks with `show_tabs`, drawn above everything
+    tab_bars: HashMap<PanelId, Vec<Drawable>>,
+    /// Windows of inactive stack children, with where to put them back
+    hidden_windows: Vec<(Window, Point<i32, Logical>)>,
+    /// Toplevels that haven't made their first commit, so aren't sized or mapped yet
+    unplaced_windows: Vec<Window>,
+    stray_windows: StrayWindows,
+    /// Children of `Absolute` displays, drawn above everything else
+    overlays: Vec<PanelId>,
+    /// Client windows belonging to overlays, kept raised above the rest
+    overlay_windows: Vec<Window>,
+    /// The panel that gets keyboard focus whenever it has a window. Unset, the first
+    /// window to appear takes it.
+    focus: Option<PanelId>,
+    reload_shortcut: Shortcut,
+    quit_shortcut: Shortcut,
+    next_shortcut: Shortcut,
+    refresh_shortcut: Shortcut,
+    lock_shortcut: Shortcut,
+    screenshot_shortcut: Shortcut,
+    /// Where shortcut screenshots are saved
+    screenshot_dir: PathBuf,
+    /// Waiting for the next frame of each output
+    screenshot: Option<screenshot::Pending>,
+    /// Every key press is swallowed except the lock shortcut. Clients get no other input
+    /// to begin with.
+    locked: bool,
+    /// `locked` from the config as last loaded, so a reload only overrides the lock
+    /// shortcut when the setting itself changed
+    config_locked: bool,
+    /// Set by SIGTERM, SIGINT or the quit shortcut; checked at the top of the main loop
+    shutdown: Arc<AtomicBool>,
+    start_time: Instant,
+    suppressed_keys: Vec<Keycode>,
+    pointer_location: Point<f64, Logical>,
     data_device_state: DataDeviceState,
     shm_state: ShmState,
     seat: Seat<Self>,
@@ -43,46 +623,199 @@ pub struct App {
 #[derive(Default)]
 pub struct ClientState {
     compositor_state: CompositorClientState,
+    /// The panel whose process connected, if it's one of ours. Set once connected.
+    panel: OnceLock<PanelId>,
 }
 
 impl ClientData for ClientState {
-    fn initialized(&self, _client_id: ClientId) {
-        println!("initialized");
-    }
+    fn initialized(&self, _client_id: ClientId) {}
 
-    fn disconnected(&self, _client_id: ClientId, _reason: DisconnectReason) {
-        println!("disconnected");
+    fn disconnected(&self, client_id: ClientId, reason: DisconnectReason) {
+        match self.panel.get() {
+            Some(panel) => info!(client = ?client_id, %panel, ?reason, "Client disconnected"),
+            None => info!(client = ?client_id, ?reason, "Client disconnected"),
+        }
     }
 }
 
 impl App {
-    fn new() -> Self {
-        let display: WlDisplay<ClientState> = WlDisplay::new().expect("Failed to create display");
-        let display_handle = display.handle();
+    fn with_config(
+        display_handle: DisplayHandle,
+        loop_handle: LoopHandle<'static, App>,
+        socket_name: OsString,
+        args: cli::Args,
+    ) -> Self {
+        let config_path = config::resolve_path(args.config);
+        info!("Using config '{}'", config_path.display());
 
         let compositor_state = CompositorState::new::<Self>(&display_handle);
         let xdg_shell_state = XdgShellState::new::<Self>(&display_handle);
         let mut seat_state = SeatState::new();
 
-        let displays = match fs::read_to_string("config.json") {
-            Ok(content) => serde_json::from_str(&content).expect("Failed to parse config"),
+        let config = match config::load(&config_path) {
+            Ok(config) => config,
+            Err(config::LoadError::Io(err)) => {
+                warn!("Failed to read '{}': {}", config_path.display(), err);
+                Config::default()
+            }
+            // Keep running so the watcher can pick up a fixed file
             Err(err) => {
-                eprintln!("Warning: Failed to read 'config.json': {}", err);
-                HashMap::new()
+                error!(
+                    "Failed to parse '{}', starting with no displays: {}",
+                    config_path.display(),
+                    err
+                );
+                Config::default()
             }
         };
+        let config = match config.validate() {
+            Ok(()) => config,
+            Err(errors) => {
+                report_config_errors(&config_path, &errors);
+                Config::default()
+            }
+        };
+
+        let config_watcher = match config::watch(&config_path, &loop_handle) {
+            Ok(watcher) => Some(watcher),
+            Err(err) => {
+                warn!(
+                    "Failed to watch '{}' for changes: {}",
+                    config_path.display(),
+                    err
+                );
+                None
+            }
+        };
+
+        let max_depth = config.max_depth();
+        let max_windows = config.max_windows();
+        let control_socket = args.control_socket.or_else(|| config.control_socket());
+        let control =
+            control_socket.and_then(|path| match control::Server::bind(&path, &loop_handle) {
+                Ok(server) => Some(server),
+                Err(err) => {
+                    warn!(
+                        "Failed to open control socket '{}': {}",
+                        path.display(),
+                        err
+                    );
+                    None
+                }
+            });
+
+        let dbus = match config.dbus.then(|| dbus::Server::connect(&loop_handle)) {
+            Some(Ok(server)) => Some(server),
+            Some(Err(err)) => {
+                warn!("Failed to register on the session bus: {}", err);
+                None
+            }
+            None => None,
+        };
+
+        #[cfg(feature = "http")]
+        let http = config.http_address.as_deref().and_then(|address| {
+            let token = config.http_token().map(str::to_string);
+            http::Server::bind(address, token, &loop_handle)
+                .inspect_err(|err| warn!("Failed to serve the HTTP API on '{}': {}", address, err))
+                .ok()
+        });
+
+        // Start set so anything that exits before the first poll is still reaped
+        let child_exited = Arc::new(AtomicBool::new(true));
+        signal_hook::flag::register(signal_hook::consts::SIGCHLD, Arc::clone(&child_exited))
+            .expect("Failed to install SIGCHLD handler");
+
+        let hangup = Arc::new(AtomicBool::new(false));
+        signal_hook::flag::register(signal_hook::consts::SIGHUP, Arc::clone(&hangup))
+            .expect("Failed to install SIGHUP handler");
+
+        let shutdown = Arc::new(AtomicBool::new(false));
+        for signal in [signal_hook::consts::SIGTERM, signal_hook::consts::SIGINT] {
+            signal_hook::flag::register(signal, Arc::clone(&shutdown))
+                .expect("Failed to install signal handler");
+        }
+
+        let signals = [
+            signal_hook::consts::SIGCHLD,
+            signal_hook::consts::SIGHUP,
+            signal_hook::consts::SIGTERM,
+            signal_hook::consts::SIGINT,
+        ];
+        wake_on_signals(&loop_handle, &signals).expect("Failed to watch for signals");
 
         let data_device_state = DataDeviceState::new::<Self>(&display_handle);
         let shm_state = ShmState::new::<Self>(&display_handle, vec![]);
-        let seat = seat_state.new_wl_seat(&display_handle, "pickle");
+        let mut seat = seat_state.new_wl_seat(&display_handle, "pickle");
+        seat.add_keyboard(XkbConfig::default(), 200, 25)
+            .expect("Failed to add keyboard to seat");
+        seat.add_pointer();
 
         Self {
             display_handle,
+            loop_handle,
+            socket_name,
             space: Space::default(),
             compositor_state,
             xdg_shell_state,
             seat_state,
-            displays,
+            displays: config.displays,
+            root_rects: HashMap::new(),
+            default_display: config.default_display,
+            default_rects: HashMap::new(),
+            mirror: config.mirror,
+            mirror_rects: HashMap::new(),
+            browser: config.browser,
+            background: config.background,
+            output_configs: config.outputs,
+            wallpaper: config.wallpaper,
+            wallpapers: HashMap::new(),
+            loading: config.loading,
+            placeholders: HashMap::new(),
+            config_path,
+            _config_watcher: config_watcher,
+            config_changed: false,
+            persist: config.persist,
+            saved_config: None,
+            hangup,
+            _control: control,
+            _dbus: dbus,
+            #[cfg(feature = "http")]
+            _http: http,
+            drawables: HashMap::new(),
+            children: HashMap::new(),
+            child_exited,
+            pending_restarts: HashMap::new(),
+            stopping: HashMap::new(),
+            restart_backoff: args.restart_backoff,
+            max_restarts: args.max_restarts,
+            max_depth,
+            max_windows,
+            refreshes: HashMap::new(),
+            slideshows: HashMap::new(),
+            stacks: HashMap::new(),
+            schedules: HashMap::new(),
+            tab_bars: HashMap::new(),
+            hidden_windows: Vec::new(),
+            unplaced_windows: Vec::new(),
+            stray_windows: config.stray_windows,
+            overlays: Vec::new(),
+            overlay_windows: Vec::new(),
+            focus: config.focus,
+            reload_shortcut: args.reload_shortcut,
+            quit_shortcut: args.quit_shortcut,
+            next_shortcut: args.next_shortcut,
+            refresh_shortcut: args.refresh_shortcut,
+            lock_shortcut: args.lock_shortcut,
+            screenshot_shortcut: args.screenshot_shortcut,
+            screenshot_dir: args.screenshot_dir,
+            screenshot: None,
+            locked: args.locked || config.locked,
+            config_locked: config.locked,
+            shutdown,
+            start_time: Instant::now(),
+            suppressed_keys: Vec::new(),
+            pointer_location: Point::default(),
             data_device_state,
             shm_state,
             seat,
@@ -90,79 +823,1244 @@ impl App {
     }
 
     fn spawn_configured_windows(&mut self) {
-        for (id, display) in self.displays.clone() {
-            self.spawn_display(id, &display, None);
+        self.update_wallpapers();
+        for (id, root) in self.displays.clone() {
+            self.spawn_root(id, &root);
         }
+        self.sync_default_display();
     }
 
-    fn spawn_display(
+    fn spawn_root(&mut self, id: u32, root: &Root) {
+        let Some(output) = self.root_output(id, root) else {
+            info!("No outputs connected, pausing display {}", id);
+            self.root_rects.remove(&id);
+            return;
+        };
+        let Some(window_info) = self.output_geometry(&output) else {
+            info!("Output has no mode yet, pausing display {}", id);
+            self.root_rects.remove(&id);
+            return;
+        };
+
+        {
+            // Everything logged while spawning the tree says which screen it's on
+            let _span = info_span!("spawn_root", display = id, output = output.name()).entered();
+            self.root_rects.insert(id, window_info);
+            self.spawn_display(&PanelId::root(id), &root.display, window_info);
+        }
+        self.spawn_mirrors(id, root);
+    }
+
+    /// The copies top-level display `id` needs on the other mirrored outputs, should it
+    /// be on the first, along with where each goes. Outputs not connected get none.
+    fn mirror_geometries(&self, id: u32, root: &Root) -> Vec<(PanelId, (i32, i32, i32, i32))> {
+        let Some((primary, mirrors)) = self.mirror.split_first() else {
+            return Vec::new();
+        };
+        let on_primary = self
+            .root_output(id, root)
+            .is_some_and(|output| output.name() == *primary);
+        if !on_primary {
+            return Vec::new();
+        }
+
+        mirrors
+            .iter()
+            .filter_map(|name| {
+                let output = self.space.outputs().find(|output| output.name() == *name)?;
+                let window_info = self.output_geometry(output)?;
+                Some((PanelId::mirrored(id, name), window_info))
+            })
+            .collect()
+    }
+
+    fn spawn_mirrors(&mut self, id: u32, root: &Root) {
+        for (mirror_id, window_info) in self.mirror_geometries(id, root) {
+            let output = mirror_id.output.clone().unwrap_or_default();
+            let _span = info_span!("spawn_root", display = id, output).entered();
+            self.mirror_rects.insert(mirror_id.clone(), window_info);
+            self.spawn_display(&mirror_id, &root.display, window_info);
+        }
+    }
+
+    /// The mirrored copies of top-level display `id` running now, with where they are.
+    fn mirrors_of(&self, id: u32) -> Vec<(PanelId, (i32, i32, i32, i32))> {
+        let mut mirrors: Vec<(PanelId, (i32, i32, i32, i32))> = self
+            .mirror_rects
+            .iter()
+            .filter(|(mirror_id, _)| mirror_id.root == Some(id))
+            .map(|(mirror_id, rect)| (mirror_id.clone(), *rect))
+            .collect();
+        mirrors.sort_by(|(a, _), (b, _)| a.output.cmp(&b.output));
+        mirrors
+    }
+
+    fn despawn_mirrors(&mut self, id: u32, display: &Display) {
+        for (mirror_id, _) in self.mirrors_of(id) {
+            self.mirror_rects.remove(&mirror_id);
+            self.despawn_display(&mirror_id, display);
+        }
+    }
+
+    /// Respawns the mirrored copies of every display whose mirrors no longer match the
+    /// outputs, such as after a mirrored output was plugged in.
+    fn sync_mirrors(&mut self) {
+        for (id, root) in self.displays.clone() {
+            let mut wanted = self.mirror_geometries(id, &root);
+            wanted.sort_by(|(a, _), (b, _)| a.output.cmp(&b.output));
+            if wanted == self.mirrors_of(id) {
+                continue;
+            }
+
+            info!("Mirrored outputs changed, laying display {} out again", id);
+            self.despawn_mirrors(id, &root.display);
+            self.spawn_mirrors(id, &root);
+        }
+    }
+
+    /// Logs a newly connected client along with the panel whose process it is, which is
+    /// remembered for when it disconnects.
+    fn client_connected(&self, client: &Client) {
+        let pid = client
+            .get_credentials(&self.display_handle)
+            .map(|credentials| credentials.pid)
+            .ok();
+        let panel = pid.and_then(|pid| self.panel_for_pid(pid as u32));
+
+        match (client.get_data::<ClientState>(), panel) {
+            (Some(state), Some(panel)) => {
+                info!(client = ?client.id(), pid, %panel, "Client connected");
+                let _ = state.panel.set(panel);
+            }
+            _ => info!(client = ?client.id(), pid, "Client connected"),
+        }
+    }
+
+    /// Lays every top-level display out again after an output was added, removed or
+    /// resized, respawning those that now belong somewhere else.
+    pub fn outputs_changed(&mut self) {
+        self.prune_screenshot();
+        self.update_wallpapers();
+        for (id, root) in self.displays.clone() {
+            if self.root_geometry(id, &root).as_ref() == self.root_rects.get(&id) {
+                continue;
+            }
+
+            info!("Outputs changed, moving display {}", id);
+            self.despawn_mirrors(id, &root.display);
+            self.despawn_display(&PanelId::root(id), &root.display);
+            self.spawn_root(id, &root);
+        }
+        self.sync_mirrors();
+        self.sync_default_display();
+    }
+
+    /// Lays the default display out on every output nothing else is on, and takes it off
+    /// those that have since been given a display of their own.
+    fn sync_default_display(&mut self) {
+        let Some(display) = self.default_display.clone() else {
+            return;
+        };
+
+        let mut occupied: Vec<String> = self
+            .displays
+            .iter()
+            .filter_map(|(id, root)| self.root_output(*id, root))
+            .map(|output| output.name())
+            .collect();
+        occupied.extend(self.mirror_rects.keys().filter_map(|id| id.output.clone()));
+        let wanted: HashMap<String, (i32, i32, i32, i32)> = self
+            .space
+            .outputs()
+            .filter(|output| !occupied.contains(&output.name()))
+            .filter_map(|output| Some((output.name(), self.output_geometry(output)?)))
+            .collect();
+
+        let stale: Vec<String> = self
+            .default_rects
+            .iter()
+            .filter(|(output, rect)| wanted.get(*output) != Some(rect))
+            .map(|(output, _)| output.clone())
+            .collect();
+        for output in stale {
+            self.default_rects.remove(&output);
+            self.despawn_display(&PanelId::fallback(&output), &display);
+        }
+
+        for (output, window_info) in wanted {
+            if self.default_rects.contains_key(&output) {
+                continue;
+            }
+
+            let _span = info_span!("spawn_root", display = "default", output).entered();
+            info!("Showing the default display");
+            self.default_rects.insert(output.clone(), window_info);
+            self.spawn_display(&PanelId::fallback(&output), &display, window_info);
+        }
+    }
+
+    fn despawn_default_display(&mut self) {
+        let Some(display) = self.default_display.clone() else {
+            return;
+        };
+        for output in std::mem::take(&mut self.default_rects).into_keys() {
+            self.despawn_display(&PanelId::fallback(&output), &display);
+        }
+    }
+
+    /// Lays a top-level display out against its output. `None` while there are no usable
+    /// outputs, in which case the display is paused until one appears.
+    fn root_geometry(&self, id: u32, root: &Root) -> Option<(i32, i32, i32, i32)> {
+        self.root_output(id, root)
+            .and_then(|output| self.output_geometry(&output))
+    }
+
+    /// The output a top-level display goes on. Displays naming an output use it, falling
+    /// back to the first output while it's not connected. The rest are dealt out
+    /// round-robin across outputs in id order, leaving out those mirroring another.
+    fn root_output(&self, id: u32, root: &Root) -> Option<Output> {
+        let outputs: Vec<Output> = self.space.outputs().cloned().collect();
+
+        let output = match &root.output {
+            Some(name) => outputs
+                .iter()
+                .find(|output| output.name() == *name)
+                .or_else(|| {
+                    warn!(
+                        "Output '{}' for display {} is not connected, using the first output",
+                        name, id
+                    );
+                    outputs.first()
+                }),
+            None => {
+                // Mirrored outputs past the first only show copies of its displays
+                let mirrors = self.mirror.get(1..).unwrap_or_default();
+                let outputs: Vec<&Output> = outputs
+                    .iter()
+                    .filter(|output| !mirrors.contains(&output.name()))
+                    .collect();
+                let position = self
+                    .displays
+                    .iter()
+                    .filter(|(other, root)| **other < id && root.output.is_none())
+                    .count();
+                outputs.get(position % outputs.len().max(1)).copied()
+            }
+        };
+
+        output.cloned()
+    }
+
+    /// The output's area in logical coordinates, which is what clients size themselves in.
+    /// `None` for outputs that have no mode set yet.
+    fn output_geometry(&self, output: &Output) -> Option<(i32, i32, i32, i32)> {
+        logical_geometry(&self.space, output)
+    }
+
+    /// Applies the configured scale and rotation to `output`. Outputs without any are left
+    /// as the backend set them up.
+    pub(crate) fn configure_output(&self, output: &Output) {
+        let Some(config) = self.output_configs.get(&output.name()) else {
+            return;
+        };
+
+        let scale = match config.scale {
+            Some(scale) if scale.is_finite() && scale > 0.0 => scale,
+            Some(scale) => {
+                warn!(output = output.name(), "Ignoring invalid scale {}", scale);
+                1.0
+            }
+            None => 1.0,
+        };
+        output.change_current_state(
+            None,
+            Some(config.transform.into()),
+            Some(Scale::Fractional(scale)),
+            None,
+        );
+    }
+
+    /// Configures every output again and packs them left to right in their current
+    /// order, as their logical widths may have changed.
+    fn configure_outputs(&mut self) {
+        let mut outputs: Vec<Output> = self.space.outputs().cloned().collect();
+        outputs.sort_by_key(|output| {
+            self.space
+                .output_geometry(output)
+                .map_or(0, |geometry| geometry.loc.x)
+        });
+
+        let mut x = 0;
+        for output in outputs {
+            self.configure_output(&output);
+            output.change_current_state(None, None, None, Some((x, 0).into()));
+            self.space.map_output(&output, (x, 0));
+            x += self
+                .space
+                .output_geometry(&output)
+                .map_or(0, |geometry| geometry.size.w);
+        }
+        self.outputs_changed();
+    }
+
+    /// Reloads the config if the watcher has seen it change.
+    fn poll_config_changes(&mut self) {
+        if self.hangup.swap(false, Ordering::Relaxed) {
+            info!("Received SIGHUP, reloading config");
+            let _ = self.reload_config();
+        }
+
+        // Saves often arrive as several events, so coalesce them into one reload
+        if std::mem::take(&mut self.config_changed) {
+            if self.saved_config.is_some()
+                && fs::read_to_string(&self.config_path).ok() == self.saved_config
+            {
+                return;
+            }
+
+            // Failures are logged, and there's nobody else to report them to
+            let _ = self.reload_config();
+        }
+    }
+
+    /// Writes the running displays back to the config file.
+    fn save_config(&mut self) -> Result<(), String> {
+        let content = config::save(&self.config_path, &self.displays)?;
+        self.saved_config = Some(content);
+        Ok(())
+    }
+
+    /// Re-reads the config, respawning only the displays whose definition changed.
+    /// A config that fails to load or validate leaves the current one running.
+    fn reload_config(&mut self) -> Result<(), String> {
+        let config = match config::load(&self.config_path) {
+            Ok(config) => config,
+            Err(err) => {
+                warn!(
+                    "Failed to reload '{}', keeping previous config: {}",
+                    self.config_path.display(),
+                    err
+                );
+                return Err(err.to_string());
+            }
+        };
+        if let Err(errors) = config.validate() {
+            report_config_errors(&self.config_path, &errors);
+            return Err(join_errors(&errors));
+        }
+
+        // Every webpage may be affected by a change in browser, so start everything afresh
+        let respawn_all = config.browser != self.browser;
+        self.max_depth = config.max_depth();
+        self.persist = config.persist;
+        self.max_windows = config.max_windows();
+        if respawn_all {
+            info!("Browser settings changed, respawning all displays");
+        }
+        self.browser = config.browser;
+        self.background = config.background;
+        self.loading = config.loading;
+        self.stray_windows = config.stray_windows;
+        if config.outputs != self.output_configs {
+            info!("Output settings changed, laying outputs out again");
+            self.output_configs = config.outputs;
+            self.configure_outputs();
+        }
+        if config.wallpaper != self.wallpaper {
+            self.wallpaper = config.wallpaper;
+            self.update_wallpapers();
+        }
+        if config.locked != self.config_locked {
+            info!(locked = config.locked, "Lock setting changed");
+            self.locked = config.locked;
+            self.config_locked = config.locked;
+        }
+        if config.focus != self.focus {
+            if let Some(id) = &config.focus {
+                // The panel may not have a window yet, it'll be focused once it maps
+                let _ = self.focus_display(id);
+            }
+            self.focus = config.focus;
+        }
+        if config.default_display != self.default_display {
+            info!("Default display changed");
+            self.despawn_default_display();
+            self.default_display = config.default_display;
+        }
+        let mirror_changed = config.mirror != self.mirror;
+        self.mirror = config.mirror;
+        self.apply_displays(config.displays, respawn_all);
+        if mirror_changed {
+            info!("Mirrored outputs changed");
+            self.outputs_changed();
+        }
+        Ok(())
+    }
+
+    /// Switches over to `displays`, despawning and respawning only those that differ from
+    /// what's running, or all of them if `respawn_all` is set.
+    fn apply_displays(&mut self, displays: HashMap<u32, Root>, respawn_all: bool) {
+        let previous = std::mem::replace(&mut self.displays, displays);
+
+        // Displays that went away or moved output can't be updated in place
+        let kept = |id: &u32, old: &Root, displays: &HashMap<u32, Root>| {
+            !respawn_all && displays.get(id).is_some_and(|new| new.output == old.output)
+        };
+        for (id, old) in &previous {
+            if !kept(id, old, &self.displays) {
+                self.root_rects.remove(id);
+                self.despawn_mirrors(*id, &old.display);
+                self.despawn_display(&PanelId::root(*id), &old.display);
+            }
+        }
+
+        for (id, root) in self.displays.clone() {
+            match previous.get(&id) {
+                Some(old) if kept(&id, old, &self.displays) => {
+                    if *old != root {
+                        info!("Config changed for display {}, updating", id);
+                        // Paused displays have nothing running to update
+                        if let Some(window_info) = self.root_geometry(id, &root) {
+                            let root_id = PanelId::root(id);
+                            self.update_display(&root_id, &old.display, &root.display, window_info);
+                        }
+
+                        for (mirror_id, window_info) in self.mirrors_of(id) {
+                            self.update_display(
+                                &mirror_id,
+                                &old.display,
+                                &root.display,
+                                window_info,
+                            );
+                        }
+                    }
+                }
+                _ => {
+                    info!("Config changed for display {}, respawning", id);
+                    self.spawn_root(id, &root);
+                }
+            }
+        }
+        self.sync_default_display();
+    }
+
+    /// Brings a running display from `old` to `new`. Containers whose own layout is
+    /// unchanged are kept and their children compared one by one, so only the panels
+    /// that actually changed get respawned.
+    fn update_display(
         &mut self,
-        id: u32,
-        display: &Display,
-        window_info: Option<(i32, i32, i32, i32)>,
+        id: &PanelId,
+        old: &Display,
+        new: &Display,
+        window_info: (i32, i32, i32, i32),
     ) {
-        let window_info = window_info.unwrap_or_else(|| {
-            // Get all outputs (monitors) and their positions
-            let outputs: Vec<&Output> = self.space.outputs().collect();
-            if outputs.is_empty() {
-                // Fallback if no outputs
-                return (0, 0, 800, 600);
+        if old == new {
+            return;
+        }
+
+        if old.same_layout(new) {
+            let rects = self.child_rects(new, window_info);
+            let children = old.children().iter().zip(new.children()).zip(rects);
+            for (index, ((old_child, new_child), rect)) in children.enumerate() {
+                self.update_display(&id.child(index), old_child, new_child, rect);
             }
+            return;
+        }
 
-            // Get logical position and size of the first output
-            let o = outputs[0];
-            let info = o.current_mode().unwrap();
-            let position = self.space.output_geometry(o).unwrap();
+        // With the same URLs, a slideshow carries on from the one it's showing
+        if let (
+            Display::Slideshow { urls: old_urls, .. },
+            Display::Slideshow {
+                urls,
+                interval_secs,
+            },
+        ) = (old, new)
+            && old_urls == urls
+            && let Some(slideshow) = self.slideshows.get_mut(id)
+        {
+            if slideshow.window_info == window_info {
+                slideshow.interval = Duration::from_secs((*interval_secs).max(1));
+                slideshow.next_at = Instant::now() + slideshow.interval;
+                return;
+            }
 
-            (
-                position.loc.x,
-                position.loc.y,
-                info.size.w as i32,
-                info.size.h as i32,
-            )
+            let index = slideshow.index;
+            self.despawn_display(id, old);
+            self.spawn_slideshow(id, urls, *interval_secs, index, window_info);
+            return;
+        }
+
+        self.despawn_display(id, old);
+        self.spawn_display(id, new, window_info);
+    }
+
+    fn despawn_display(&mut self, id: &PanelId, display: &Display) {
+        match display {
+            Display::Webpage { .. } => {
+                self.refreshes.remove(id);
+                self.placeholders.remove(id);
+                self.kill_process(id);
+            }
+            Display::Command { .. } | Display::Video { .. } => self.kill_process(id),
+            Display::Slideshow { .. } => {
+                self.slideshows.remove(id);
+                self.kill_process(id);
+            }
+            Display::Image { .. }
+            | Display::SolidColor { .. }
+            | Display::Clock { .. }
+            | Display::Text { .. } => {
+                self.drawables.remove(id);
+            }
+            Display::Split { items, .. } | Display::Grid { items, .. } => {
+                for (index, item) in items.iter().enumerate() {
+                    self.despawn_display(&id.child(index), item);
+                }
+            }
+            Display::Schedule { .. } => self.despawn_schedule(id),
+            Display::Stack { items, .. } => {
+                self.stacks.remove(id);
+                self.tab_bars.remove(id);
+                for (index, item) in items.iter().enumerate() {
+                    self.despawn_display(&id.child(index), item);
+                }
+            }
+            Display::Absolute { child, .. } => {
+                let child_id = id.child(0);
+                self.overlays.retain(|overlay| *overlay != child_id);
+                self.despawn_display(&child_id, child);
+            }
+        }
+    }
+
+    /// Whether a panel sits under an `Absolute`, and so belongs above everything else.
+    fn is_overlay(&self, id: &PanelId) -> bool {
+        self.overlays.iter().any(|overlay| id.is_within(overlay))
+    }
+
+    /// Finds the output an absolutely positioned panel is relative to, which is the one its
+    /// parent's rectangle starts on.
+    fn output_containing(&self, x: i32, y: i32) -> Option<(i32, i32, i32, i32)> {
+        let outputs: Vec<Output> = self.space.outputs().cloned().collect();
+        outputs
+            .iter()
+            .filter_map(|output| self.output_geometry(output))
+            .find(|(ox, oy, width, height)| {
+                x >= *ox && y >= *oy && x < ox + width && y < oy + height
+            })
+            .or_else(|| {
+                outputs
+                    .first()
+                    .and_then(|output| self.output_geometry(output))
+            })
+    }
+
+    /// Reloads images whose files changed and redraws clocks whose time moved on.
+    fn refresh_drawables(&mut self) {
+        for drawable in self
+            .drawables
+            .values_mut()
+            .chain(self.wallpapers.values_mut())
+        {
+            match drawable {
+                Drawable::Image(image) => image.refresh(),
+                Drawable::Clock(clock) => clock.refresh(),
+                Drawable::Solid { .. } | Drawable::Label { .. } => {}
+            }
+        }
+    }
+
+    /// Scales the wallpaper to cover each output as it is now.
+    fn update_wallpapers(&mut self) {
+        self.wallpapers.clear();
+        let Some(path) = &self.wallpaper else {
+            return;
+        };
+
+        for output in self.space.outputs() {
+            if let Some(geometry) = self.space.output_geometry(output) {
+                let image = Image::new(path.clone(), ImageFit::Cover, geometry);
+                self.wallpapers
+                    .insert(output.name(), Drawable::Image(image));
+            }
+        }
+    }
+
+    /// Relaunches every webpage whose refresh interval has elapsed.
+    fn refresh_webpages(&mut self) {
+        let now = Instant::now();
+        let due: Vec<PanelId> = self
+            .refreshes
+            .iter_mut()
+            .filter(|(_, refresh)| refresh.next_at <= now)
+            .map(|(id, refresh)| {
+                refresh.next_at = now + refresh.interval;
+                id.clone()
+            })
+            .collect();
+
+        for id in due {
+            self.refresh_panel(&id);
+        }
+    }
+
+    /// Relaunches the process behind panel `id`. Returns `false` if it has none running,
+    /// which includes processes waiting on a crash restart, as those come back fresh anyway.
+    fn refresh_panel(&mut self, id: &PanelId) -> bool {
+        let Some((display, window_info)) = self.process_display(id) else {
+            return false;
+        };
+
+        info!(%id, "Refreshing display");
+        self.kill_process(id);
+        self.spawn_display(id, &display, window_info);
+        true
+    }
+
+    /// Moves every slideshow whose interval has elapsed on to its next URL. Firefox offers
+    /// no way to navigate an existing window from outside, so the browser is respawned.
+    fn advance_slideshows(&mut self) {
+        let now = Instant::now();
+        let due: Vec<PanelId> = self
+            .slideshows
+            .iter()
+            .filter(|(_, slideshow)| slideshow.next_at <= now)
+            .map(|(id, _)| id.clone())
+            .collect();
+
+        for id in due {
+            self.advance_slideshow(&id, now);
+        }
+    }
+
+    fn advance_slideshow(&mut self, id: &PanelId, now: Instant) {
+        let Some(slideshow) = self.slideshows.get_mut(id) else {
+            return;
+        };
+
+        slideshow.index = (slideshow.index + 1) % slideshow.urls.len();
+        slideshow.next_at = now + slideshow.interval;
+        let url = slideshow.urls[slideshow.index].clone();
+        let window_info = slideshow.window_info;
+
+        self.kill_process(id);
+        self.spawn_webpage(id, &url, None, None, None, window_info);
+    }
+
+    /// When the earliest timer is due, be it a refresh, slideshow, stack, schedule,
+    /// restart or kill. Images and clocks are checked at the start of every second.
+    fn next_deadline(&self) -> Option<Instant> {
+        let timers = self
+            .refreshes
+            .values()
+            .map(|refresh| refresh.next_at)
+            .chain(self.slideshows.values().map(|slideshow| slideshow.next_at))
+            .chain(self.stacks.values().map(|stack| stack.next_at))
+            .chain(self.next_schedule_check())
+            .chain(self.next_process_deadline());
+
+        let redrawn = self
+            .drawables
+            .values()
+            .chain(self.wallpapers.values())
+            .any(|drawable| matches!(drawable, Drawable::Image(_) | Drawable::Clock(_)));
+        let next_second = redrawn.then(|| {
+            let since_epoch = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default();
+            Instant::now() + Duration::from_secs(1)
+                - Duration::from_nanos(since_epoch.subsec_nanos().into())
         });
 
+        timers.chain(next_second).min()
+    }
+
+    /// Swaps each stack whose interval has elapsed over to its next child. The child it
+    /// leaves keeps running with its windows hidden, so it can come back instantly.
+    fn advance_stacks(&mut self) {
+        let now = Instant::now();
+        let due: Vec<PanelId> = self
+            .stacks
+            .iter()
+            .filter(|(_, stack)| stack.next_at <= now)
+            .map(|(id, _)| id.clone())
+            .collect();
+
+        for id in due {
+            self.advance_stack(&id, now);
+        }
+    }
+
+    fn advance_stack(&mut self, id: &PanelId, now: Instant) {
+        let Some(stack) = self.stacks.get_mut(id) else {
+            return;
+        };
+
+        stack.index = (stack.index + 1) % stack.items.len();
+        stack.next_at = now + stack.interval;
+
+        self.update_tab_bar(id);
+        self.sync_hidden_windows();
+    }
+
+    /// Whether the panel sits under a stack child that isn't the active one.
+    fn is_hidden(&self, id: &PanelId) -> bool {
+        self.stacks.iter().any(|(stack_id, stack)| {
+            id != stack_id && id.is_within(stack_id) && !id.is_within(&stack_id.child(stack.index))
+        })
+    }
+
+    /// Unmaps the windows of panels that just became hidden and maps back those that are
+    /// visible again, where they were before.
+    fn sync_hidden_windows(&mut self) {
+        let hiding: Vec<Window> = self
+            .space
+            .elements()
+            .filter(|window| {
+                window
+                    .wl_surface()
+                    .and_then(|surface| self.panel_for_surface(&surface))
+                    .is_some_and(|id| self.is_hidden(&id))
+            })
+            .cloned()
+            .collect();
+        for window in hiding {
+            let location = self.space.element_location(&window).unwrap_or_default();
+            self.space.unmap_elem(&window);
+            self.hidden_windows.push((window, location));
+        }
+
+        self.hidden_windows.retain(|(window, _)| window.alive());
+        let (showing, hidden): (Vec<_>, Vec<_>) = std::mem::take(&mut self.hidden_windows)
+            .into_iter()
+            .partition(|(window, _)| {
+                window
+                    .wl_surface()
+                    .and_then(|surface| self.panel_for_surface(&surface))
+                    .is_none_or(|id| !self.is_hidden(&id))
+            });
+        self.hidden_windows = hidden;
+        for (window, location) in showing {
+            self.space.map_element(window, location, false);
+        }
+
+        for window in &self.overlay_windows {
+            self.space.raise_element(window, false);
+        }
+    }
+
+    /// Sizes a new client window to its panel's rectangle and maps it there. Windows whose
+    /// panel can't be found go in the top-left corner at whatever size they choose.
+    pub(crate) fn place_window(&mut self, window: Window) {
+        let Some(toplevel) = window.toplevel() else {
+            return;
+        };
+        let wl_surface = toplevel.wl_surface().clone();
+        let panel = self.panel_for_surface(&wl_surface);
+
+        let rect = panel.as_ref().and_then(|id| self.process_display(id));
+        let location = match rect {
+            Some((_, (x, y, width, height))) => {
+                toplevel.with_pending_state(|state| state.size = Some((width, height).into()));
+                Point::from((x, y))
+            }
+            None if self.stray_windows == StrayWindows::Close => {
+                warn!(surface = ?wl_surface.id(), "Window belongs to no panel, closing it");
+                toplevel.send_close();
+                return;
+            }
+            None => {
+                warn!(
+                    surface = ?wl_surface.id(),
+                    "Window belongs to no panel, placing it at the origin"
+                );
+                Point::from((0, 0))
+            }
+        };
+        toplevel.send_configure();
+
+        let overlay = panel.as_ref().is_some_and(|id| self.is_overlay(id));
+        let focus = match &self.focus {
+            Some(focus) => panel.as_ref().is_some_and(|id| id.is_within(focus)),
+            None => self
+                .seat
+                .get_keyboard()
+                .is_some_and(|keyboard| keyboard.current_focus().is_none()),
+        };
+
+        // Windows of inactive stack children wait out of sight until their turn
+        if panel.as_ref().is_some_and(|id| self.is_hidden(id)) {
+            self.hidden_windows.push((window, location));
+            return;
+        }
+        self.space.map_element(window.clone(), location, false);
+        if overlay {
+            self.overlay_windows.push(window);
+        }
+
+        // Newly mapped windows go on top, so put the overlays back above them
+        self.overlay_windows.retain(|window| window.alive());
+        for window in &self.overlay_windows {
+            self.space.raise_element(window, false);
+        }
+
+        if focus && let Some(keyboard) = self.seat.get_keyboard() {
+            keyboard.set_focus(self, Some(wl_surface), SERIAL_COUNTER.next_serial());
+        }
+    }
+
+    /// Redraws the tab bar of stack `id` to highlight its active child.
+    fn update_tab_bar(&mut self, id: &PanelId) {
+        let Some(stack) = self.stacks.get(id).filter(|stack| stack.show_tabs) else {
+            return;
+        };
+
+        let (x, y, width, _) = stack.window_info;
+        let rect = Rectangle::new((x, y).into(), (width, draw::TAB_BAR_HEIGHT).into());
+        let bar = draw::tab_bar(rect, stack.items.len(), stack.index);
+        self.tab_bars.insert(id.clone(), bar);
+    }
+
+    fn spawn_webpage(
+        &mut self,
+        id: &PanelId,
+        url: &str,
+        browser: Option<&str>,
+        args_template: Option<&[String]>,
+        profile: Option<&str>,
+        window_info: (i32, i32, i32, i32),
+    ) {
+        let command = self.browser_command(id, url, browser, args_template, profile, window_info);
+        let display = Display::Webpage {
+            url: url.to_string(),
+            browser: browser.map(String::from),
+            args_template: args_template.map(|args| args.to_vec()),
+            refresh_secs: None,
+            profile: profile.map(String::from),
+        };
+        self.spawn_process(id.clone(), command, display, window_info);
+        // A browser that failed to start has an error showing instead
+        if self.children.contains_key(id) {
+            self.show_placeholder(id, window_info);
+        }
+    }
+
+    /// The browser command showing `url` in panel `id`, creating its profile directory.
+    fn browser_command(
+        &self,
+        id: &PanelId,
+        url: &str,
+        browser: Option<&str>,
+        args_template: Option<&[String]>,
+        profile: Option<&str>,
+        window_info: (i32, i32, i32, i32),
+    ) -> Command {
+        // Settings on the panel itself win over the global ones
+        let kind = self.browser.kind;
+        let executable = browser
+            .or(self.browser.executable.as_deref())
+            .unwrap_or(kind.executable());
+        info!(browser = executable, url, "Spawning browser");
+
+        let default_args = kind.args_template();
+        let template = args_template
+            .or(self.browser.args_template.as_deref())
+            .unwrap_or(&default_args);
+
+        // Separate profiles keep a crash or session-restore prompt in one panel from
+        // spilling into the others
+        let profile_dir = match profile {
+            Some(profile) => PathBuf::from(profile),
+            None => self.profiles_dir().join(format!("profile_{}", id)),
+        };
+        if let Err(err) = fs::create_dir_all(&profile_dir) {
+            warn!(
+                "Failed to create profile '{}': {}",
+                profile_dir.display(),
+                err
+            );
+        }
+
+        let (x, y, width, height) = window_info;
+        let values = [
+            ("url", url.to_string()),
+            ("class", id.app_id()),
+            ("profile", profile_dir.display().to_string()),
+            ("x", x.to_string()),
+            ("y", y.to_string()),
+            ("width", width.to_string()),
+            ("height", height.to_string()),
+        ];
+
+        let mut command = Command::new(executable);
+        command.args(
+            template
+                .iter()
+                .chain(&self.browser.kiosk_flags)
+                .map(|arg| process::expand(arg, &values)),
+        );
+        command
+    }
+
+    /// Starts a slideshow on `urls[index]`.
+    fn spawn_slideshow(
+        &mut self,
+        id: &PanelId,
+        urls: &[String],
+        interval_secs: u64,
+        index: usize,
+        window_info: (i32, i32, i32, i32),
+    ) {
+        let Some(url) = urls.get(index) else {
+            warn!("Slideshow has no URLs");
+            return;
+        };
+
+        self.spawn_webpage(id, url, None, None, None, window_info);
+
+        let interval = Duration::from_secs(interval_secs.max(1));
+        self.slideshows.insert(
+            id.clone(),
+            Slideshow {
+                urls: urls.to_vec(),
+                index,
+                interval,
+                next_at: Instant::now() + interval,
+                window_info,
+            },
+        );
+    }
+
+    /// Covers a webpage panel with the loading placeholder until its window draws.
+    fn show_placeholder(&mut self, id: &PanelId, window_info: (i32, i32, i32, i32)) {
+        let (x, y, width, height) = window_info;
+        let rect = Rectangle::new((x, y).into(), (width, height).into());
+
+        let mut placeholder = Vec::new();
+        if !self.loading.text.is_empty() {
+            match text::load_font(None) {
+                Ok(font) => placeholder.push(Drawable::label(
+                    &self.loading.text,
+                    &font,
+                    self.loading.color,
+                    rect,
+                )),
+                Err(err) => warn!("Failed to load placeholder font {}", err),
+            }
+        }
+        placeholder.push(Drawable::solid(rect, self.loading.background.0));
+        self.placeholders.insert(id.clone(), placeholder);
+    }
+
+    /// Where browser profiles for this instance live. Keyed by socket name so that several
+    /// compositors on one machine don't share profiles.
+    pub(crate) fn profiles_dir(&self) -> PathBuf {
+        let base = env::var_os("XDG_RUNTIME_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(env::temp_dir);
+        base.join("screens").join(&self.socket_name)
+    }
+
+    /// The rectangles a container places its children in, in the same order as
+    /// [`Display::children`]. Grid cells past `rows * cols` get none.
+    fn child_rects(
+        &self,
+        display: &Display,
+        window_info: (i32, i32, i32, i32),
+    ) -> Vec<(i32, i32, i32, i32)> {
+        match display {
+            Display::Split {
+                vertical,
+                items,
+                sizes,
+                weights,
+                gap,
+                margin,
+            } => layout::split_rects(
+                window_info,
+                *vertical,
+                items.len(),
+                sizes,
+                weights,
+                *gap,
+                *margin,
+            ),
+            Display::Grid {
+                rows,
+                cols,
+                items,
+                gap,
+                margin,
+            } => {
+                let (start_x, start_y, total_width, total_height) =
+                    layout::inset(window_info, *margin);
+                let columns = layout::divide(total_width, *cols as usize, &[], *gap);
+                let rows = layout::divide(total_height, *rows as usize, &[], *gap);
+                let cells = columns.len() * rows.len();
+
+                (0..items.len().min(cells))
+                    .map(|index| {
+                        let (column_offset, column_width) = columns[index % columns.len()];
+                        let (row_offset, row_height) = rows[index / columns.len()];
+                        (
+                            start_x + column_offset,
+                            start_y + row_offset,
+                            column_width,
+                            row_height,
+                        )
+                    })
+                    .collect()
+            }
+            Display::Absolute {
+                x,
+                y,
+                width,
+                height,
+                ..
+            } => {
+                let (output_x, output_y, output_width, output_height) = self
+                    .output_containing(window_info.0, window_info.1)
+                    .unwrap_or((0, 0, i32::MAX, i32::MAX));
+
+                let left = (*x).clamp(0, output_width);
+                let top = (*y).clamp(0, output_height);
+                let right = x.saturating_add(*width).clamp(left, output_width);
+                let bottom = y.saturating_add(*height).clamp(top, output_height);
+                if (left, top, right - left, bottom - top) != (*x, *y, *width, *height) {
+                    warn!(
+                        "Absolute rectangle {}x{}+{}+{} doesn't fit its {}x{} output, clamping it",
+                        width, height, x, y, output_width, output_height
+                    );
+                }
+
+                vec![(output_x + left, output_y + top, right - left, bottom - top)]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn spawn_display(
+        &mut self,
+        id: &PanelId,
+        display: &Display,
+        window_info: (i32, i32, i32, i32),
+    ) {
+        let (x, y, width, height) = window_info;
+        let kind = display.kind();
+        let _span = info_span!("spawn_display", %id, kind, x, y, width, height).entered();
+
         match display {
-            Display::Webpage { url } => {
-                println!("Spawning Firefox for URL: {}", url);
-                let window_class = format!("firefox_window_{}", id);
-
-                let (x, y, width, height) = window_info;
-                let mut command = std::process::Command::new("firefox");
-                command.args([
-                    "--new-window",
+            Display::Webpage {
+                url,
+                browser,
+                args_template,
+                refresh_secs,
+                profile,
+            } => {
+                self.spawn_webpage(
+                    id,
                     url,
-                    "--class",
-                    &window_class,
-                    "--width",
-                    &width.to_string(),
-                    "--height",
-                    &height.to_string(),
-                    "--geometry",
-                    &format!("{}x{}+{}+{}", width, height, x, y),
-                ]);
-
-                let _ = command.spawn();
-            }
-            Display::Split { vertical, items } => {
-                let (start_x, start_y, total_width, total_height) = window_info;
-                let total_items = items.len();
+                    browser.as_deref(),
+                    args_template.as_deref(),
+                    profile.as_deref(),
+                    window_info,
+                );
 
-                for (index, item) in items.iter().enumerate() {
-                    // Calculate subdivision size and position
-                    let sub_info = if *vertical {
-                        let height = total_height / total_items as i32;
-                        let y = start_y + (index as i32 * height);
-                        Some((start_x, y, total_width, height))
-                    } else {
-                        let width = total_width / total_items as i32;
-                        let x = start_x + (index as i32 * width);
-                        Some((x, start_y, width, total_height))
-                    };
+                if let Some(secs) = refresh_secs {
+                    let interval = Duration::from_secs((*secs).max(1));
+                    self.refreshes.insert(
+                        id.clone(),
+                        Refresh {
+                            interval,
+                            next_at: Instant::now() + interval,
+                        },
+                    );
+                }
+            }
+            Display::Command { exec, args } => {
+                info!(exec, "Spawning command");
+
+                // Clients can't be positioned from outside, so hand them their rectangle
+                let values = [
+                    ("class", id.app_id()),
+                    ("x", x.to_string()),
+                    ("y", y.to_string()),
+                    ("width", width.to_string()),
+                    ("height", height.to_string()),
+                ];
+                let mut command = Command::new(exec);
+                command
+                    .args(args.iter().map(|arg| process::expand(arg, &values)))
+                    .env("SCREENS_X", x.to_string())
+                    .env("SCREENS_Y", y.to_string())
+                    .env("SCREENS_W", width.to_string())
+                    .env("SCREENS_H", height.to_string());
+
+                self.spawn_process(id.clone(), command, display.clone(), window_info);
+            }
+            Display::Video {
+                path,
+                loop_playback,
+                player,
+                args_template,
+            } => {
+                let player = player.as_deref().unwrap_or("mpv");
+                info!(player, path, "Spawning video player");
+
+                let default_args = VIDEO_ARGS.map(String::from);
+                let template = args_template.as_deref().unwrap_or(&default_args);
+                let values = [
+                    ("path", path.clone()),
+                    ("class", id.app_id()),
+                    ("x", x.to_string()),
+                    ("y", y.to_string()),
+                    ("width", width.to_string()),
+                    ("height", height.to_string()),
+                ];
+
+                let mut command = Command::new(player);
+                command.args(template.iter().map(|arg| process::expand(arg, &values)));
+                if *loop_playback {
+                    command.arg("--loop-file=inf");
+                }
+                command.arg(path);
+
+                self.spawn_process(id.clone(), command, display.clone(), window_info);
+            }
+            Display::Slideshow {
+                urls,
+                interval_secs,
+            } => self.spawn_slideshow(id, urls, *interval_secs, 0, window_info),
+            Display::Image { path, fit } => {
+                let rect = Rectangle::new((x, y).into(), (width, height).into());
+                let image = Image::new(PathBuf::from(path), *fit, rect);
+                self.drawables.insert(id.clone(), Drawable::Image(image));
+            }
+            Display::Clock {
+                format,
+                color,
+                font,
+            } => {
+                let rect = Rectangle::new((x, y).into(), (width, height).into());
+                let font = font.as_deref().map(Path::new);
+                let clock = Clock::new(format.clone(), font, *color, rect);
+                self.drawables
+                    .insert(id.clone(), Drawable::Clock(Box::new(clock)));
+            }
+            Display::Text {
+                content,
+                color,
+                background,
+                align,
+                size,
+                font,
+            } => {
+                let rect = Rectangle::new((x, y).into(), (width, height).into());
+                // Nothing bigger than the panel could be shown anyway
+                let px = size
+                    .unwrap_or((height / 12).clamp(16, 96) as f32)
+                    .min(height.max(1) as f32);
+                let drawable = match text::load_font(font.as_deref().map(Path::new)) {
+                    Ok(font) => {
+                        Drawable::text(content, &font, px, (*color, *background), *align, rect)
+                    }
+                    Err(err) => {
+                        warn!("Failed to load text font {}", err);
+                        Drawable::solid(rect, ERROR_COLOR)
+                    }
+                };
+                self.drawables.insert(id.clone(), drawable);
+            }
+            Display::SolidColor { color } => {
+                let rect = Rectangle::new((x, y).into(), (width, height).into());
+                self.drawables
+                    .insert(id.clone(), Drawable::solid(rect, color.0));
+            }
+            Display::Split {
+                items,
+                sizes,
+                weights,
+                ..
+            } => {
+                // Validation rejects these, but never lay out nothing
+                if items.is_empty() {
+                    warn!("Split has no items");
+                    return;
+                }
+                if !weights.is_empty() && weights.len() != items.len() {
+                    warn!(
+                        "Split has {} weights for {} items, missing weights default to 1",
+                        weights.len(),
+                        items.len()
+                    );
+                }
+                if !sizes.is_empty() && sizes.len() != items.len() {
+                    warn!(
+                        "Split has {} sizes for {} items, missing sizes default to Fill",
+                        sizes.len(),
+                        items.len()
+                    );
+                }
+                let percent: f32 = sizes
+                    .iter()
+                    .filter_map(|size| match size {
+                        SplitSize::Percent(percent) => Some(*percent),
+                        _ => None,
+                    })
+                    .sum();
+                if percent > 100.0 {
+                    warn!(
+                        "Split percentages add up to {}%, scaling them down to fit",
+                        percent
+                    );
+                }
 
-                    // Generate unique ID for subdivision
-                    let sub_id = id * 100 + index as u32;
-                    self.spawn_display(sub_id, item, sub_info);
+                let rects = self.child_rects(display, window_info);
+                for (index, (item, rect)) in items.iter().zip(rects).enumerate() {
+                    self.spawn_display(&id.child(index), item, rect);
+                }
+            }
+            Display::Grid { items, .. } => {
+                let rects = self.child_rects(display, window_info);
+                for (index, (item, rect)) in items.iter().zip(rects).enumerate() {
+                    self.spawn_display(&id.child(index), item, rect);
+                }
+            }
+            Display::Absolute { child, .. } => {
+                let child_id = id.child(0);
+                self.overlays.push(child_id.clone());
+                if let Some(rect) = self.child_rects(display, window_info).pop() {
+                    self.spawn_display(&child_id, child, rect);
+                }
+            }
+            Display::Schedule { entries } => self.spawn_schedule(id, entries, window_info),
+            Display::Stack {
+                interval_secs,
+                items,
+                show_tabs,
+            } => {
+                if items.is_empty() {
+                    warn!("Stack has no items");
+                    return;
+                }
+
+                // Registered first, so children know they're hidden as they spawn
+                let interval = Duration::from_secs((*interval_secs).max(1));
+                self.stacks.insert(
+                    id.clone(),
+                    Stack {
+                        items: items.clone(),
+                        index: 0,
+                        interval,
+                        next_at: Instant::now() + interval,
+                        window_info,
+                        show_tabs: *show_tabs,
+                    },
+                );
+                self.update_tab_bar(id);
+
+                for (index, item) in items.iter().enumerate() {
+                    self.spawn_display(&id.child(index), item, window_info);
                 }
             }
         }
@@ -174,16 +2072,427 @@ delegate_compositor!(App);
 delegate_shm!(App);
 delegate_seat!(App);
 delegate_data_device!(App);
+delegate_output!(App);
+
+fn default_clock_format() -> String {
+    "%H:%M".to_string()
+}
+
+/// `output`'s area in `space` in logical coordinates. The space takes care of dividing
+/// by the scale and swapping width and height for rotated outputs.
+fn logical_geometry(space: &Space<Window>, output: &Output) -> Option<(i32, i32, i32, i32)> {
+    let geometry = space.output_geometry(output)?;
+    Some((
+        geometry.loc.x,
+        geometry.loc.y,
+        geometry.size.w,
+        geometry.size.h,
+    ))
+}
+
+/// Wakes the event loop whenever one of `signals` arrives, so the flags their handlers
+/// set are acted on straight away.
+fn wake_on_signals(handle: &LoopHandle<'static, App>, signals: &[c_int]) -> io::Result<()> {
+    let (reader, writer) = UnixStream::pair()?;
+    reader.set_nonblocking(true)?;
+    writer.set_nonblocking(true)?;
+    for signal in signals {
+        signal_hook::low_level::pipe::register(*signal, writer.try_clone()?)?;
+    }
+
+    let source = Generic::new(reader, Interest::READ, Mode::Level);
+    handle
+        .insert_source(source, |_, reader, _: &mut App| {
+            // Which signals arrived is up to the flags, so the bytes themselves don't matter
+            let mut buffer = [0; 64];
+            while matches!(reader.as_ref().read(&mut buffer), Ok(read) if read > 0) {}
+            Ok(PostAction::Continue)
+        })
+        .map_err(|err| io::Error::other(err.error))?;
+    Ok(())
+}
+
+fn default_clock_color() -> Color {
+    Color::WHITE
+}
+
+fn join_errors(errors: &[config::ConfigError]) -> String {
+    errors
+        .iter()
+        .map(|err| err.to_string())
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// Logs every validation problem, since fixing them one reload at a time is tedious.
+fn report_config_errors(path: &Path, errors: &[config::ConfigError]) {
+    error!(
+        "Config '{}' has {} problem(s), not spawning it",
+        path.display(),
+        errors.len()
+    );
+    for err in errors {
+        error!("{}", err);
+    }
+}
+
+enum Backend {
+    Winit(Box<winit::Backend>),
+    Drm(Box<drm::Backend>),
+}
+
+impl Backend {
+    /// Handles pending backend events. Window events are handled by the event loop
+    /// itself, so only the DRM backend has any.
+    fn dispatch(&mut self, app: &mut App) {
+        if let Backend::Drm(backend) = self {
+            backend.dispatch(app);
+        }
+    }
+
+    fn render(&mut self, app: &mut App) {
+        match self {
+            Backend::Winit(backend) => backend.render(app),
+            Backend::Drm(backend) => backend.render(app),
+        }
+    }
+}
 
 fn main() {
-    let mut wm = App::new();
+    let args = cli::Args::parse();
+    // Checked before logging starts, so the report is all that's printed
+    if let Some(path) = &args.check {
+        std::process::exit(config::check(path, args.check_format));
+    }
+
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .init();
+
+    let display: WlDisplay<App> = WlDisplay::new().expect("Failed to create display");
+    let mut event_loop: EventLoop<'static, App> =
+        EventLoop::try_new().expect("Failed to create event loop");
+    let socket = ListeningSocketSource::new_auto().expect("Failed to bind a Wayland socket");
+    info!(
+        "Listening on Wayland socket '{}'",
+        socket.socket_name().to_string_lossy()
+    );
+
+    let backend_kind = args.backend;
+    let mut wm = App::with_config(
+        display.handle(),
+        event_loop.handle(),
+        socket.socket_name().to_owned(),
+        args,
+    );
+
+    // Clients are accepted and their requests handled as soon as they arrive, rather than
+    // waiting for the next frame
+    let handle = event_loop.handle();
+    handle
+        .insert_source(socket, |stream, _, app: &mut App| {
+            match app
+                .display_handle
+                .insert_client(stream, Arc::new(ClientState::default()))
+            {
+                Ok(client) => app.client_connected(&client),
+                Err(err) => warn!("Failed to insert client: {}", err),
+            }
+        })
+        .expect("Failed to watch the Wayland socket");
+    handle
+        .insert_source(
+            Generic::new(display, Interest::READ, Mode::Level),
+            |_, display, app: &mut App| {
+                // Safety: the display is never dropped while the source is registered
+                if let Err(err) = unsafe { display.get_mut() }.dispatch_clients(app) {
+                    warn!("Failed to dispatch clients: {}", err);
+                }
+                Ok(PostAction::Continue)
+            },
+        )
+        .expect("Failed to watch the Wayland display");
+
+    let mut backend = match backend_kind {
+        cli::BackendKind::Winit => Backend::Winit(Box::new(winit::Backend::new(&mut wm))),
+        cli::BackendKind::Drm => Backend::Drm(Box::new(drm::Backend::new(&mut wm))),
+    };
     wm.spawn_configured_windows();
 
-    // Main event loop with minimal window management
-    loop {
-        // Keep the display alive but don't allow window movements
+    // Sleeps until a source has something for us or a timer is due. Anything that wakes
+    // us may change what's on screen, so a frame follows, though never sooner than
+    // FRAME_INTERVAL after the last one.
+    let mut next_frame = Instant::now();
+    let mut redraw = true;
+    while !wm.shutdown.load(Ordering::Relaxed) {
+        let deadline = wm
+            .next_deadline()
+            .into_iter()
+            .chain(redraw.then_some(next_frame))
+            .min();
+        let timeout = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        if let Err(err) = event_loop.dispatch(timeout, &mut wm) {
+            warn!("Failed to dispatch events: {}", err);
+        }
+
+        backend.dispatch(&mut wm);
+        wm.poll_config_changes();
+        wm.refresh_drawables();
+        wm.refresh_webpages();
+        wm.advance_slideshows();
+        wm.advance_stacks();
+        wm.advance_schedules();
+        wm.reap_children();
+        if let Err(err) = wm.display_handle.flush_clients() {
+            warn!("Failed to flush clients: {}", err);
+        }
+        redraw = true;
+
+        if Instant::now() < next_frame {
+            continue;
+        }
+        next_frame = Instant::now() + FRAME_INTERVAL;
+        redraw = false;
+
+        backend.render(&mut wm);
         wm.display_handle.flush_clients().expect("Failed to flush");
-        // Optional: Add a small sleep to prevent CPU spinning
-        std::thread::sleep(std::time::Duration::from_millis(16));
+    }
+
+    info!("Shutting down");
+    wm.terminate_children();
+    // Let clients see their toplevels closing before the socket goes away
+    if let Err(err) = wm.display_handle.flush_clients() {
+        warn!("Failed to flush clients: {}", err);
+    }
+    drop(wm);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+
+    use smithay::output::{Mode, PhysicalProperties, Subpixel};
+
+    use super::*;
+    use crate::config::OutputTransform;
+
+    /// An app started from `config`, written to a file named after the test using it.
+    /// The display and event loop have to outlive the app.
+    fn app(test: &str, config: &str) -> (WlDisplay<App>, EventLoop<'static, App>, App) {
+        let path = env::temp_dir().join(format!("screens-{}-{}.json", std::process::id(), test));
+        fs::write(&path, config).unwrap();
+
+        let display = WlDisplay::new().unwrap();
+        let event_loop = EventLoop::try_new().unwrap();
+        let args = cli::Args {
+            config: Some(path.clone()),
+            ..cli::Args::default()
+        };
+        let app = App::with_config(
+            display.handle(),
+            event_loop.handle(),
+            "screens-test".into(),
+            args,
+        );
+        let _ = fs::remove_file(&path);
+        (display, event_loop, app)
+    }
+
+    fn output(name: &str, transform: OutputTransform) -> Output {
+        let output = Output::new(
+            name.to_string(),
+            PhysicalProperties {
+                size: (0, 0).into(),
+                subpixel: Subpixel::Unknown,
+                make: "screens".into(),
+                model: "test".into(),
+            },
+        );
+        let mode = Mode {
+            size: (1920, 1080).into(),
+            refresh: 60_000,
+        };
+        output.change_current_state(
+            Some(mode),
+            Some(transform.into()),
+            None,
+            Some((0, 0).into()),
+        );
+        output
+    }
+
+    #[test]
+    fn rotated_outputs_swap_width_and_height() {
+        let cases = [
+            (OutputTransform::Normal, (1920, 1080)),
+            (OutputTransform::Rotate90, (1080, 1920)),
+            (OutputTransform::Rotate180, (1920, 1080)),
+            (OutputTransform::Rotate270, (1080, 1920)),
+        ];
+        for (transform, (width, height)) in cases {
+            let mut space = Space::<Window>::default();
+            let output = output("test", transform);
+            space.map_output(&output, (0, 0));
+
+            assert_eq!(
+                logical_geometry(&space, &output),
+                Some((0, 0, width, height))
+            );
+        }
+    }
+
+    #[test]
+    fn displays_wait_for_an_output_connected_later() {
+        let config = r#"{ "displays": { "1": { "SolidColor": { "color": "red" } } } }"#;
+        let (_display, _event_loop, mut app) = app("late-output", config);
+
+        app.spawn_configured_windows();
+        assert!(app.root_rects.is_empty());
+        assert!(!app.drawables.contains_key(&PanelId::root(1)));
+
+        let output = output("HDMI-A-1", OutputTransform::Normal);
+        app.space.map_output(&output, (0, 0));
+        app.outputs_changed();
+        assert_eq!(app.root_rects.get(&1), Some(&(0, 0, 1920, 1080)));
+        assert!(app.drawables.contains_key(&PanelId::root(1)));
+    }
+
+    #[test]
+    fn only_timers_in_use_wake_the_loop() {
+        let (_display, _event_loop, mut idle) = app("idle", r#"{ "displays": {} }"#);
+        idle.spawn_configured_windows();
+        assert_eq!(idle.next_deadline(), None);
+
+        let config = r#"{ "displays": { "1": { "Clock": {} } } }"#;
+        let (_clock_display, _clock_loop, mut clock) = app("clock", config);
+        clock.spawn_configured_windows();
+        clock
+            .space
+            .map_output(&output("HDMI-A-1", OutputTransform::Normal), (0, 0));
+        clock.outputs_changed();
+
+        let deadline = clock.next_deadline().expect("The clock needs redrawing");
+        assert!(deadline <= Instant::now() + Duration::from_secs(1));
+    }
+
+    #[test]
+    fn control_requests_are_answered_as_they_arrive() {
+        let (_display, mut event_loop, mut app) = app("control", r#"{ "displays": {} }"#);
+        let path = env::temp_dir().join(format!("screens-{}-control.sock", std::process::id()));
+        let _server = control::Server::bind(&path, &event_loop.handle()).unwrap();
+
+        let mut client = UnixStream::connect(&path).unwrap();
+        client.write_all(b"{\"status\": {}}\n").unwrap();
+        // Once to accept the connection, then once to read the request off it
+        for _ in 0..2 {
+            event_loop
+                .dispatch(Some(Duration::from_secs(1)), &mut app)
+                .unwrap();
+        }
+
+        let mut reply = String::new();
+        BufReader::new(&client).read_line(&mut reply).unwrap();
+        let reply: serde_json::Value = serde_json::from_str(&reply).unwrap();
+        assert_eq!(reply["ok"], true);
+    }
+
+    #[test]
+    fn webpages_get_their_own_profiles() {
+        let (_display, _event_loop, app) = app("profiles", r#"{ "displays": {} }"#);
+        let rect = (0, 0, 800, 600);
+        let profile_arg = |command: Command| {
+            let args: Vec<_> = command.get_args().map(|arg| arg.to_owned()).collect();
+            let index = args.iter().position(|arg| arg == "--profile").unwrap();
+            args[index + 1].clone()
+        };
+
+        let url = "https://example.com";
+        let first = app.browser_command(&PanelId::root(1), url, None, None, None, rect);
+        let second = app.browser_command(&PanelId::root(2), url, None, None, None, rect);
+        let shared = env::temp_dir().join("screens-test-profile");
+        let named = app.browser_command(&PanelId::root(3), url, None, None, shared.to_str(), rect);
+
+        let (first, second, named) = (profile_arg(first), profile_arg(second), profile_arg(named));
+        assert_ne!(first, second);
+        assert_eq!(named, shared.as_os_str());
+        assert!(first != named && second != named);
+        let _ = fs::remove_dir(&shared);
+    }
+
+    #[test]
+    fn app_ids_name_their_panels() {
+        let nested = PanelId::root(3).child(1).child(0);
+        assert_eq!(nested.app_id(), "screens_panel_3.1.0");
+        assert_eq!(PanelId::from_app_id("screens_panel_3.1.0"), Some(nested));
+        assert_eq!(
+            PanelId::from_app_id("screens_panel_3@HDMI-A-2"),
+            Some(PanelId::mirrored(3, "HDMI-A-2"))
+        );
+        assert_eq!(
+            PanelId::from_app_id("screens_panel_default@HDMI-A-1"),
+            Some(PanelId::fallback("HDMI-A-1"))
+        );
+
+        for malformed in [
+            "screens_panel_",
+            "screens_panel_x",
+            "screens_panel_3.",
+            "screens_panel_3@",
+            "screens_panel_default",
+            "other_3",
+            "",
+        ] {
+            assert_eq!(PanelId::from_app_id(malformed), None, "{}", malformed);
+        }
+    }
+
+    #[test]
+    fn max_windows_caps_the_processes_spawned() {
+        let item = serde_json::json!({ "Command": { "exec": "sleep", "args": ["60"] } });
+        let config = serde_json::json!({
+            "max_windows": 4,
+            "displays": {
+                "1": { "Split": { "vertical": false, "items": vec![item; 1000] } }
+            }
+        });
+        let (_display, _event_loop, mut app) = app("max-windows", &config.to_string());
+        let output = output("HDMI-A-1", OutputTransform::Normal);
+        app.space.map_output(&output, (0, 0));
+
+        app.spawn_configured_windows();
+        let spawned = app.children.len();
+        let refused = app.placeholders.len();
+        app.terminate_children();
+
+        assert_eq!(spawned, 4);
+        assert_eq!(refused, 996);
+    }
+
+    #[test]
+    fn refreshes_wait_for_the_old_process_to_exit() {
+        let config =
+            r#"{ "displays": { "1": { "Command": { "exec": "sleep", "args": ["60"] } } } }"#;
+        let (_display, _event_loop, mut app) = app("refresh", config);
+        let output = output("HDMI-A-1", OutputTransform::Normal);
+        app.space.map_output(&output, (0, 0));
+        app.spawn_configured_windows();
+
+        let id = PanelId::root(1);
+        assert!(app.refresh_panel(&id));
+        let waited = !app.children.contains_key(&id) && app.stopping.contains_key(&id);
+
+        // sleep exits on SIGTERM straight away, and its replacement starts once it has
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !app.children.contains_key(&id) && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(20));
+            app.reap_children();
+        }
+        let respawned = app.children.contains_key(&id) && app.stopping.is_empty();
+        app.terminate_children();
+
+        assert!(waited);
+        assert!(respawned);
     }
 }
@@ -0,0 +1,506 @@
+use std::{
+    io::{self, BufRead, BufReader, Read},
+    os::unix::process::CommandExt,
+    process::{Child, Command, Stdio},
+    sync::atomic::Ordering,
+    thread,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Local};
+use smithay::{
+    utils::Rectangle,
+    wayland::{compositor, shell::xdg::XdgToplevelSurfaceData},
+};
+use tracing::{error, info, warn};
+use wayland_server::{Resource, protocol::wl_surface::WlSurface};
+
+use crate::{
+    App, Display, PanelId,
+    draw::{Color, Drawable, ERROR_COLOR},
+    text,
+};
+
+/// Backoff between restarts never grows beyond this.
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(300);
+/// A process that stays up this long is considered healthy again.
+const HEALTHY_UPTIME: Duration = Duration::from_secs(60);
+/// How long children get to exit after SIGTERM before they're killed outright.
+const TERMINATE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A running client process along with what's needed to bring it back if it dies.
+pub struct Process {
+    child: Child,
+    display: Display,
+    window_info: (i32, i32, i32, i32),
+    started_at: Instant,
+    restarts: u32,
+    /// When the panel's process was last brought back after exiting
+    last_restart: Option<DateTime<Local>>,
+}
+
+impl Process {
+    /// Signals the child's whole process group, so helpers it forked (browser content
+    /// processes, wrapper scripts' children) go down with it.
+    fn signal(&self, signal: libc::c_int) {
+        // SAFETY: kill has no memory safety requirements. The child leads its own group and
+        // hasn't been reaped, so the group id can't have been reused
+        unsafe { libc::kill(-(self.child.id() as libc::pid_t), signal) };
+    }
+
+    fn kill(&mut self) {
+        self.signal(libc::SIGKILL);
+        let _ = self.child.wait();
+    }
+}
+
+/// A child that's been sent SIGTERM, killed outright if it's still running at `deadline`.
+pub struct Stopping {
+    process: Process,
+    deadline: Instant,
+}
+
+pub struct PendingRestart {
+    display: Display,
+    window_info: (i32, i32, i32, i32),
+    at: Instant,
+    restarts: u32,
+    last_restart: Option<DateTime<Local>>,
+}
+
+/// Logs each line `reader` produces as coming from panel `id`, on a thread of its own
+/// that ends when the child closes the stream.
+fn forward_output(id: &PanelId, stream: &'static str, reader: impl Read + Send + 'static) {
+    let id = id.clone();
+    thread::spawn(move || {
+        for line in BufReader::new(reader).lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            info!(panel = %id, stream, "{}", line);
+        }
+    });
+}
+
+/// Replaces each `{name}` in `template` with its value from `values`. Unknown
+/// placeholders are left untouched, and substituted values aren't scanned again, so a
+/// URL containing `{width}` is passed through as is.
+pub fn expand(template: &str, values: &[(&str, String)]) -> String {
+    let mut expanded = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let value = rest.find('}').and_then(|end| {
+            let name = &rest[1..end];
+            let (_, value) = values.iter().find(|(known, _)| *known == name)?;
+            Some((value, end))
+        });
+        match value {
+            Some((value, end)) => {
+                expanded.push_str(value);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                expanded.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+impl App {
+    /// Spawns `command` as the client for display `id`. `display` is the leaf that
+    /// gets respawned at `window_info` should the process exit.
+    pub(crate) fn spawn_process(
+        &mut self,
+        id: PanelId,
+        mut command: Command,
+        display: Display,
+        window_info: (i32, i32, i32, i32),
+    ) {
+        // The panel's previous process may still be saving its profile, so only start
+        // afresh once it's gone
+        if self.stopping.contains_key(&id) {
+            let (restarts, last_restart) =
+                self.pending_restarts.get(&id).map_or((0, None), |pending| {
+                    (pending.restarts, pending.last_restart)
+                });
+            self.pending_restarts.insert(
+                id,
+                PendingRestart {
+                    display,
+                    window_info,
+                    at: Instant::now(),
+                    restarts,
+                    last_restart,
+                },
+            );
+            return;
+        }
+
+        // Point clients at our socket rather than whatever session we were started from
+        command.env("WAYLAND_DISPLAY", &self.socket_name);
+        // Output goes through our log, tagged with the panel it came from
+        command.stdout(Stdio::piped()).stderr(Stdio::piped());
+
+        // Give each client its own process group so it can be torn down as a unit, and have
+        // the kernel terminate it should we die without getting to clean up
+        command.process_group(0);
+        // SAFETY: prctl is async-signal-safe and touches no state shared with the parent
+        unsafe {
+            command.pre_exec(|| {
+                if libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGTERM) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+
+        // Children waiting to restart count too, as they'll be back
+        let others = self.children.len()
+            + self
+                .pending_restarts
+                .keys()
+                .filter(|pending| **pending != id)
+                .count();
+        if others >= self.max_windows {
+            error!(
+                %id,
+                max_windows = self.max_windows,
+                "Too many processes, not spawning this one"
+            );
+            self.pending_restarts.remove(&id);
+            self.show_spawn_error(&id, window_info, "Too many windows");
+            return;
+        }
+
+        let (restarts, last_restart) = match self.pending_restarts.remove(&id) {
+            Some(pending) => (pending.restarts, Some(Local::now())),
+            None => (0, None),
+        };
+
+        match command.spawn() {
+            Ok(mut child) => {
+                if let Some(stdout) = child.stdout.take() {
+                    forward_output(&id, "stdout", stdout);
+                }
+                if let Some(stderr) = child.stderr.take() {
+                    forward_output(&id, "stderr", stderr);
+                }
+                self.children.insert(
+                    id,
+                    Process {
+                        child,
+                        display,
+                        window_info,
+                        started_at: Instant::now(),
+                        restarts,
+                        last_restart,
+                    },
+                );
+            }
+            Err(err) => {
+                let program = command.get_program().to_string_lossy();
+                let message = if err.kind() == io::ErrorKind::NotFound {
+                    let hint = match display {
+                        Display::Webpage { .. } | Display::Slideshow { .. } => {
+                            "set `browser.executable` in the config, or `browser` on the \
+                             Webpage, to a browser that is installed"
+                        }
+                        _ => "check it is installed and on PATH",
+                    };
+                    error!(%id, %program, "'{}' was not found, {}", program, hint);
+                    format!("{} is not installed", program)
+                } else {
+                    error!(%id, %program, "Failed to spawn process: {}", err);
+                    format!("{} failed to start", program)
+                };
+
+                // Leave something on screen so the panel doesn't just look empty
+                self.show_spawn_error(&id, window_info, &message);
+            }
+        }
+    }
+
+    /// Covers a panel whose process couldn't be started with an error color and
+    /// `message`, until it's respawned or removed.
+    fn show_spawn_error(&mut self, id: &PanelId, window_info: (i32, i32, i32, i32), message: &str) {
+        let (x, y, width, height) = window_info;
+        let rect = Rectangle::new((x, y).into(), (width, height).into());
+
+        let mut placeholder = Vec::new();
+        match text::load_font(None) {
+            Ok(font) => placeholder.push(Drawable::label(message, &font, Color::WHITE, rect)),
+            Err(err) => warn!("Failed to load error placeholder font {}", err),
+        }
+        placeholder.push(Drawable::solid(rect, ERROR_COLOR));
+        self.placeholders.insert(id.clone(), placeholder);
+    }
+
+    /// The leaf display and geometry a running process was spawned for.
+    pub(crate) fn process_display(&self, id: &PanelId) -> Option<(Display, (i32, i32, i32, i32))> {
+        self.children
+            .get(id)
+            .map(|process| (process.display.clone(), process.window_info))
+    }
+
+    /// Whether panel `id` has a child process that hasn't been seen to exit, and when it
+    /// was last restarted. `None` for panels without a process, running or pending.
+    pub(crate) fn process_state(&self, id: &PanelId) -> Option<(bool, Option<DateTime<Local>>)> {
+        if let Some(process) = self.children.get(id) {
+            return Some((true, process.last_restart));
+        }
+        self.pending_restarts
+            .get(id)
+            .map(|pending| (false, pending.last_restart))
+    }
+
+    /// The panel whose process has the given pid, used to tell which panel a client is.
+    pub(crate) fn panel_for_pid(&self, pid: u32) -> Option<PanelId> {
+        self.children
+            .iter()
+            .find(|(_, process)| process.child.id() == pid)
+            .map(|(id, _)| id.clone())
+    }
+
+    /// The running panel named by `surface`'s app id, or failing that the one whose
+    /// process owns its client. Browsers that hand windows to an already running
+    /// instance can only be matched by app id.
+    pub(crate) fn panel_for_surface(&self, surface: &WlSurface) -> Option<PanelId> {
+        let app_id = compositor::with_states(surface, |states| {
+            states
+                .data_map
+                .get::<XdgToplevelSurfaceData>()
+                .and_then(|data| data.lock().ok()?.app_id.clone())
+        });
+        if let Some(id) = app_id.as_deref().and_then(PanelId::from_app_id)
+            && self.children.contains_key(&id)
+        {
+            return Some(id);
+        }
+
+        surface
+            .client()
+            .and_then(|client| client.get_credentials(&self.display_handle).ok())
+            .and_then(|credentials| self.panel_for_pid(credentials.pid as u32))
+    }
+
+    pub(crate) fn kill_process(&mut self, id: &PanelId) {
+        self.pending_restarts.remove(id);
+        // The error placeholder from a failed spawn, or what's left of a finished video
+        self.placeholders.remove(id);
+        self.drawables.remove(id);
+        // Browsers get the chance to write out their profile rather than coming back to a
+        // crash-restore prompt
+        if let Some(process) = self.children.remove(id) {
+            process.signal(libc::SIGTERM);
+            let stopping = Stopping {
+                process,
+                deadline: Instant::now() + TERMINATE_TIMEOUT,
+            };
+            if let Some(mut previous) = self.stopping.insert(id.clone(), stopping) {
+                previous.process.kill();
+            }
+        }
+    }
+
+    /// Asks every child to exit with SIGTERM, escalating to SIGKILL for any that are
+    /// still running once [`TERMINATE_TIMEOUT`] has passed.
+    pub(crate) fn terminate_children(&mut self) {
+        self.pending_restarts.clear();
+        for (id, stopping) in self.stopping.drain() {
+            self.children.insert(id, stopping.process);
+        }
+        if self.children.is_empty() {
+            return;
+        }
+
+        info!("Terminating {} child processes", self.children.len());
+        for process in self.children.values() {
+            process.signal(libc::SIGTERM);
+        }
+
+        let deadline = Instant::now() + TERMINATE_TIMEOUT;
+        while Instant::now() < deadline {
+            self.children
+                .retain(|_, process| matches!(process.child.try_wait(), Ok(None)));
+            if self.children.is_empty() {
+                return;
+            }
+
+            std::thread::sleep(Duration::from_millis(50));
+        }
+
+        for (id, mut process) in self.children.drain() {
+            warn!(%id, "Process ignored SIGTERM, killing it");
+            process.kill();
+        }
+    }
+
+    /// Forgets children that have exited since being asked to, and kills those that
+    /// have had until their deadline.
+    fn collect_stopped(&mut self, now: Instant) {
+        self.stopping.retain(|id, stopping| {
+            if !matches!(stopping.process.child.try_wait(), Ok(None)) {
+                return false;
+            }
+            if now < stopping.deadline {
+                return true;
+            }
+
+            warn!(%id, "Process ignored SIGTERM, killing it");
+            stopping.process.kill();
+            false
+        });
+    }
+
+    /// Collects exited children and respawns them once their backoff has elapsed.
+    /// The backoff doubles with each consecutive crash so a page that keeps failing
+    /// can't swamp the machine.
+    pub(crate) fn reap_children(&mut self) {
+        let now = Instant::now();
+
+        // Clear the flag before polling so an exit racing with the scan is caught next time
+        if self.child_exited.swap(false, Ordering::Relaxed) {
+            self.collect_exited(now);
+        }
+        self.collect_stopped(now);
+
+        let due: Vec<PanelId> = self
+            .pending_restarts
+            .iter()
+            .filter(|(id, pending)| pending.at <= now && !self.stopping.contains_key(*id))
+            .map(|(id, _)| id.clone())
+            .collect();
+
+        for id in due {
+            let Some(pending) = self.pending_restarts.get(&id) else {
+                continue;
+            };
+
+            info!(%id, restarts = pending.restarts, "Respawning display");
+            let (display, window_info) = (pending.display.clone(), pending.window_info);
+            self.spawn_display(&id, &display, window_info);
+        }
+    }
+
+    /// When a panel is next due to be respawned, or a child that was asked to exit is due
+    /// to be killed. Panels waiting on their previous process to exit are woken by its
+    /// SIGCHLD instead.
+    pub(crate) fn next_process_deadline(&self) -> Option<Instant> {
+        let restarts = self
+            .pending_restarts
+            .iter()
+            .filter(|(id, _)| !self.stopping.contains_key(*id))
+            .map(|(_, pending)| pending.at);
+        let kills = self.stopping.values().map(|stopping| stopping.deadline);
+
+        restarts.chain(kills).min()
+    }
+
+    /// Removes children that have exited and schedules their restarts with backoff.
+    fn collect_exited(&mut self, now: Instant) {
+        let exited: Vec<(PanelId, bool)> = self
+            .children
+            .iter_mut()
+            .filter_map(|(id, process)| match process.child.try_wait() {
+                Ok(Some(status)) => {
+                    info!(%id, %status, "Process exited");
+                    Some((id.clone(), status.success()))
+                }
+                Ok(None) => None,
+                Err(err) => {
+                    warn!(%id, "Failed to poll process: {}", err);
+                    None
+                }
+            })
+            .collect();
+
+        for (id, success) in exited {
+            let Some(process) = self.children.remove(&id) else {
+                continue;
+            };
+
+            // A video that played to the end is done, not crashed
+            if success
+                && matches!(
+                    process.display,
+                    Display::Video {
+                        loop_playback: false,
+                        ..
+                    }
+                )
+            {
+                info!(%id, "Video finished");
+                let (x, y, width, height) = process.window_info;
+                let rect = Rectangle::new((x, y).into(), (width, height).into());
+                self.drawables
+                    .insert(id, Drawable::solid(rect, self.background.0));
+                continue;
+            }
+
+            let restarts = if now - process.started_at >= HEALTHY_UPTIME {
+                0
+            } else {
+                process.restarts + 1
+            };
+            if self.max_restarts.is_some_and(|max| restarts > max) {
+                error!(%id, restarts = process.restarts, "Process keeps crashing, giving up");
+                continue;
+            }
+
+            let delay = self
+                .restart_backoff
+                .saturating_mul(2u32.saturating_pow(restarts.saturating_sub(1)))
+                .min(MAX_RESTART_BACKOFF);
+
+            info!(%id, ?delay, "Scheduling restart");
+            self.pending_restarts.insert(
+                id,
+                PendingRestart {
+                    display: process.display,
+                    window_info: process.window_info,
+                    at: now + delay,
+                    restarts,
+                    last_restart: process.last_restart,
+                },
+            );
+        }
+    }
+}
+
+impl Drop for App {
+    fn drop(&mut self) {
+        self.terminate_children();
+        // Profiles are only for this run, and can grow large with cached pages
+        let _ = std::fs::remove_dir_all(self.profiles_dir());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::expand;
+
+    #[test]
+    fn expand_does_not_rescan_values() {
+        let values = [
+            ("url", "https://grafana/d?var={width}&x={class}".to_string()),
+            ("width", "800".to_string()),
+            ("class", "screens_panel_0".to_string()),
+        ];
+        assert_eq!(
+            expand("{url} --width={width} {unknown}", &values),
+            "https://grafana/d?var={width}&x={class} --width=800 {unknown}"
+        );
+    }
+
+    #[test]
+    fn expand_handles_stray_braces() {
+        let values = [("x", "10".to_string())];
+        assert_eq!(expand("{{x}} {x", &values), "{10} {x");
+    }
+}
@@ -0,0 +1,137 @@
+use smithay::{
+    backend::renderer::{
+        damage::{Error as DamageError, OutputDamageTracker},
+        element::{
+            memory::MemoryRenderBufferRenderElement, render_elements,
+            solid::SolidColorRenderElement, surface::WaylandSurfaceRenderElement,
+        },
+        gles::{GlesError, GlesRenderer, GlesTarget},
+        sync::SyncPoint,
+    },
+    desktop::space::SpaceRenderElements,
+    output::Output,
+    utils::{Physical, Rectangle},
+};
+
+use crate::{App, draw::Drawable};
+
+/// A frame drawn by [`App::render_frame`], ready to present.
+pub struct Frame {
+    pub sync: SyncPoint,
+    pub damage: Vec<Rectangle<i32, Physical>>,
+}
+
+render_elements! {
+    pub PanelElement<=GlesRenderer>;
+    Space=SpaceRenderElements<GlesRenderer, WaylandSurfaceRenderElement<GlesRenderer>>,
+    Memory=MemoryRenderBufferRenderElement<GlesRenderer>,
+    Solid=SolidColorRenderElement,
+}
+
+impl App {
+    /// Collects everything visible on `output`, front to back: stack tab bars, then
+    /// compositor-drawn overlays, then client windows, then the rest of the panels the
+    /// compositor draws itself, then the wallpaper. Drawables in inactive stack children
+    /// are left out.
+    pub fn render_elements(
+        &self,
+        renderer: &mut GlesRenderer,
+        output: &Output,
+    ) -> Vec<PanelElement> {
+        let Some(geometry) = self.space.output_geometry(output) else {
+            return Vec::new();
+        };
+        let scale = output.current_scale().fractional_scale().into();
+
+        let placeholders = self
+            .placeholders
+            .iter()
+            .flat_map(|(id, drawables)| drawables.iter().map(move |drawable| (id, drawable)));
+        let (overlays, underlays): (Vec<_>, Vec<_>) = self
+            .drawables
+            .iter()
+            .chain(placeholders)
+            .filter(|(id, _)| !self.is_hidden(id))
+            .partition(|(id, _)| self.is_overlay(id));
+
+        let mut elements: Vec<PanelElement> = self
+            .tab_bars
+            .iter()
+            .filter(|(id, _)| !self.is_hidden(id))
+            .flat_map(|(_, bar)| bar)
+            .filter_map(|drawable| drawable.render_element(renderer, geometry.loc, scale))
+            .collect();
+
+        elements.extend(
+            overlays
+                .into_iter()
+                .filter_map(|(_, drawable)| drawable.render_element(renderer, geometry.loc, scale)),
+        );
+
+        elements.extend(
+            self.space
+                .render_elements_for_output(renderer, output, 1.0)
+                .unwrap_or_default()
+                .into_iter()
+                .map(PanelElement::from),
+        );
+
+        elements.extend(
+            underlays
+                .into_iter()
+                .filter_map(|(_, drawable)| drawable.render_element(renderer, geometry.loc, scale)),
+        );
+
+        // A wallpaper that failed to load leaves the plain background showing
+        if let Some(wallpaper @ Drawable::Image(image)) = self.wallpapers.get(&output.name())
+            && image.is_loaded()
+        {
+            elements.extend(wallpaper.render_element(renderer, geometry.loc, scale));
+        }
+
+        elements
+    }
+
+    /// Redraws whatever changed on `output` since `framebuffer` was last drawn, `age`
+    /// frames ago. Returns the damaged area, or `None` if nothing changed and the frame
+    /// needn't be presented. A screenshot waiting on `output` forces a full redraw, then
+    /// reads it back.
+    pub fn render_frame(
+        &mut self,
+        renderer: &mut GlesRenderer,
+        framebuffer: &mut GlesTarget<'_>,
+        damage_tracker: &mut OutputDamageTracker,
+        age: usize,
+        output: &Output,
+    ) -> Result<Option<Frame>, DamageError<GlesError>> {
+        let elements = self.render_elements(renderer, output);
+        let age = if self.awaits_screenshot(output) {
+            0
+        } else {
+            age
+        };
+
+        let result = damage_tracker.render_output(
+            renderer,
+            framebuffer,
+            age,
+            &elements,
+            self.background.0,
+        )?;
+        let Some(damage) = result.damage.cloned() else {
+            return Ok(None);
+        };
+        let sync = result.sync;
+
+        self.capture_screenshot(renderer, framebuffer, output);
+        Ok(Some(Frame { sync, damage }))
+    }
+
+    /// Lets clients on `output` know a frame was presented so they draw the next one.
+    pub fn send_frames(&self, output: &Output) {
+        let time = self.start_time.elapsed();
+        for window in self.space.elements_for_output(output) {
+            window.send_frame(output, time, None, |_, _| Some(output.clone()));
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use std::time::{Duration, Instant};
+
+use chrono::{Local, NaiveTime};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{App, Display, PanelId};
+
+/// How often schedules check whether another entry has become active.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// One child of a `Schedule` and the time of day it's shown.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct ScheduleEntry {
+    /// When the entry starts showing, as `HH:MM` local time
+    pub from: TimeOfDay,
+    /// When it stops showing. Earlier than `from` for windows past midnight, such as
+    /// `22:00` to `06:00`, and equal to it for all day.
+    pub to: TimeOfDay,
+    pub display: Box<Display>,
+}
+
+impl ScheduleEntry {
+    fn contains(&self, time: NaiveTime) -> bool {
+        let (from, to) = (self.from.0, self.to.0);
+        if from < to {
+            from <= time && time < to
+        } else {
+            // Wraps past midnight, or covers the whole day when they're equal
+            time >= from || time < to
+        }
+    }
+}
+
+/// A local time of day written as `HH:MM`.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub struct TimeOfDay(NaiveTime);
+
+impl TryFrom<String> for TimeOfDay {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        NaiveTime::parse_from_str(&value, "%H:%M")
+            .map(TimeOfDay)
+            .map_err(|_| format!("invalid time '{}', expected HH:MM", value))
+    }
+}
+
+impl From<TimeOfDay> for String {
+    fn from(time: TimeOfDay) -> Self {
+        time.0.format("%H:%M").to_string()
+    }
+}
+
+/// A schedule showing whichever entry's time window is current. Only that entry's child
+/// is spawned.
+pub struct Schedule {
+    entries: Vec<ScheduleEntry>,
+    /// The entry being shown, if any window is current
+    index: Option<usize>,
+    next_check: Instant,
+    window_info: (i32, i32, i32, i32),
+}
+
+/// The first entry whose window contains `time`. Should others match too, they're
+/// named in a warning, as they'll never be shown while the first one is.
+fn active_entry(id: &PanelId, entries: &[ScheduleEntry], time: NaiveTime) -> Option<usize> {
+    let mut matching = entries
+        .iter()
+        .enumerate()
+        .filter(|(_, entry)| entry.contains(time))
+        .map(|(index, _)| index);
+    let active = matching.next()?;
+
+    let shadowed: Vec<usize> = matching.collect();
+    if !shadowed.is_empty() {
+        warn!(
+            %id,
+            active,
+            ?shadowed,
+            "Schedule entries overlap, showing the first"
+        );
+    }
+    Some(active)
+}
+
+impl App {
+    /// Starts a schedule on whichever entry is current, leaving the panel empty if none
+    /// is.
+    pub(crate) fn spawn_schedule(
+        &mut self,
+        id: &PanelId,
+        entries: &[ScheduleEntry],
+        window_info: (i32, i32, i32, i32),
+    ) {
+        let index = active_entry(id, entries, Local::now().time());
+        self.schedules.insert(
+            id.clone(),
+            Schedule {
+                entries: entries.to_vec(),
+                index,
+                next_check: Instant::now() + CHECK_INTERVAL,
+                window_info,
+            },
+        );
+
+        if let Some(index) = index {
+            self.spawn_display(&id.child(index), &entries[index].display, window_info);
+        }
+    }
+
+    /// Tears down a schedule along with the child it's showing.
+    pub(crate) fn despawn_schedule(&mut self, id: &PanelId) {
+        let Some(schedule) = self.schedules.remove(id) else {
+            return;
+        };
+        if let Some(index) = schedule.index {
+            self.despawn_display(&id.child(index), &schedule.entries[index].display);
+        }
+    }
+
+    /// The child a schedule is showing, with its id.
+    pub(crate) fn scheduled_child(&self, id: &PanelId) -> Option<(PanelId, &Display)> {
+        let schedule = self.schedules.get(id)?;
+        let index = schedule.index?;
+        Some((id.child(index), &schedule.entries[index].display))
+    }
+
+    /// When the next schedule is due to check which entry it should show.
+    pub(crate) fn next_schedule_check(&self) -> Option<Instant> {
+        self.schedules
+            .values()
+            .map(|schedule| schedule.next_check)
+            .min()
+    }
+
+    /// Swaps every schedule whose current entry has changed over to the new one.
+    pub(crate) fn advance_schedules(&mut self) {
+        let now = Instant::now();
+        let time = Local::now().time();
+        let due: Vec<PanelId> = self
+            .schedules
+            .iter()
+            .filter(|(_, schedule)| schedule.next_check <= now)
+            .map(|(id, _)| id.clone())
+            .collect();
+
+        for id in due {
+            let Some(schedule) = self.schedules.get_mut(&id) else {
+                continue;
+            };
+            schedule.next_check = now + CHECK_INTERVAL;
+
+            let matching = schedule
+                .entries
+                .iter()
+                .position(|entry| entry.contains(time));
+            if matching == schedule.index {
+                continue;
+            }
+
+            let index = active_entry(&id, &schedule.entries, time);
+            let previous = std::mem::replace(&mut schedule.index, index);
+            let entries = schedule.entries.clone();
+            let window_info = schedule.window_info;
+
+            info!(%id, ?previous, ?index, "Schedule switching entries");
+            if let Some(previous) = previous {
+                self.despawn_display(&id.child(previous), &entries[previous].display);
+            }
+            if let Some(index) = index {
+                self.spawn_display(&id.child(index), &entries[index].display, window_info);
+            }
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use std::{
+    path::{Path, PathBuf},
+    thread,
+};
+
+use chrono::Local;
+use image::{RgbaImage, imageops};
+use smithay::{
+    backend::{
+        allocator::Fourcc,
+        renderer::{ExportMem, gles::GlesRenderer, gles::GlesTarget},
+    },
+    output::Output,
+    utils::{Physical, Point, Rectangle, Size, Transform},
+};
+use tracing::{info, warn};
+
+use crate::App;
+
+/// The width downscaled screenshots are shrunk to, keeping their aspect ratio.
+const THUMBNAIL_WIDTH: u32 = 640;
+
+/// A screenshot waiting for the next frame of each output it covers.
+pub struct Pending {
+    path: PathBuf,
+    full_res: bool,
+    /// Names of the outputs not captured yet
+    outputs: Vec<String>,
+}
+
+impl App {
+    /// Saves the next frame of every output as a PNG. Each output gets its own file,
+    /// named after `path` with the output's name added, e.g. `shot-HDMI-A-1.png`.
+    /// Unless `full_res` is set, frames are shrunk to [`THUMBNAIL_WIDTH`] wide.
+    pub fn screenshot(&mut self, path: PathBuf, full_res: bool) -> Result<(), String> {
+        let outputs: Vec<String> = self.space.outputs().map(|output| output.name()).collect();
+        if outputs.is_empty() {
+            return Err("there are no outputs to capture".to_string());
+        }
+        if self.screenshot.is_some() {
+            return Err("a screenshot is already being taken".to_string());
+        }
+
+        info!(path = %path.display(), full_res, "Taking screenshot");
+        self.screenshot = Some(Pending {
+            path,
+            full_res,
+            outputs,
+        });
+        Ok(())
+    }
+
+    /// A timestamped path in the screenshot directory, e.g.
+    /// `screens-20250101-120000.png`, which [`App::screenshot`] splits per output.
+    pub(crate) fn default_screenshot_path(&self) -> PathBuf {
+        let name = Local::now().format("screens-%Y%m%d-%H%M%S.png").to_string();
+        self.screenshot_dir.join(name)
+    }
+
+    /// Whether a screenshot is waiting on the next frame of `output`.
+    pub(crate) fn awaits_screenshot(&self, output: &Output) -> bool {
+        let name = output.name();
+        self.screenshot
+            .as_ref()
+            .is_some_and(|pending| pending.outputs.contains(&name))
+    }
+
+    /// Reads back the frame just drawn into `framebuffer` if a screenshot is waiting on
+    /// `output`. Encoding and writing happen on another thread.
+    pub(crate) fn capture_screenshot(
+        &mut self,
+        renderer: &mut GlesRenderer,
+        framebuffer: &GlesTarget<'_>,
+        output: &Output,
+    ) {
+        let name = output.name();
+        let Some(pending) = &mut self.screenshot else {
+            return;
+        };
+        let Some(index) = pending.outputs.iter().position(|pending| *pending == name) else {
+            return;
+        };
+        pending.outputs.remove(index);
+
+        let path = output_path(&pending.path, &name);
+        let full_res = pending.full_res;
+        if pending.outputs.is_empty() {
+            self.screenshot = None;
+        }
+
+        let Some(mode) = output.current_mode() else {
+            return;
+        };
+        let transform = output.current_transform();
+        let image = match read_framebuffer(renderer, framebuffer, mode.size, transform) {
+            Ok(image) => image,
+            Err(err) => {
+                warn!(
+                    output = name,
+                    "Failed to read back frame for screenshot: {}", err
+                );
+                return;
+            }
+        };
+
+        thread::spawn(move || {
+            let image = if full_res || image.width() <= THUMBNAIL_WIDTH {
+                image
+            } else {
+                let height = image.height() * THUMBNAIL_WIDTH / image.width();
+                imageops::resize(
+                    &image,
+                    THUMBNAIL_WIDTH,
+                    height.max(1),
+                    imageops::FilterType::Triangle,
+                )
+            };
+
+            match image.save_with_format(&path, image::ImageFormat::Png) {
+                Ok(()) => info!("Saved screenshot to '{}'", path.display()),
+                Err(err) => warn!("Failed to save screenshot '{}': {}", path.display(), err),
+            }
+        });
+    }
+
+    /// Stops a screenshot waiting on outputs that have gone away.
+    pub(crate) fn prune_screenshot(&mut self) {
+        let connected: Vec<String> = self.space.outputs().map(|output| output.name()).collect();
+        let Some(pending) = &mut self.screenshot else {
+            return;
+        };
+        pending.outputs.retain(|output| connected.contains(output));
+        if pending.outputs.is_empty() {
+            self.screenshot = None;
+        }
+    }
+}
+
+fn read_framebuffer(
+    renderer: &mut GlesRenderer,
+    framebuffer: &GlesTarget<'_>,
+    size: Size<i32, Physical>,
+    transform: Transform,
+) -> Result<RgbaImage, String> {
+    let region = Rectangle::from_size((size.w, size.h).into());
+    let mapping = renderer
+        .copy_framebuffer(framebuffer, region, Fourcc::Abgr8888)
+        .map_err(|err| err.to_string())?;
+    let pixels = renderer
+        .map_texture(&mapping)
+        .map_err(|err| err.to_string())?;
+
+    let image = RgbaImage::from_raw(size.w as u32, size.h as u32, pixels.to_vec())
+        .ok_or_else(|| "framebuffer is smaller than the output".to_string())?;
+
+    Ok(untransform(&image, transform))
+}
+
+/// Turns a frame drawn for an output with `transform` back the way it's seen, so a portrait
+/// screen mounted on its side gives a portrait screenshot. The winit backend's `Flipped180`
+/// undoes the window's bottom-left origin.
+fn untransform(image: &RgbaImage, transform: Transform) -> RgbaImage {
+    if transform == Transform::Normal {
+        return image.clone();
+    }
+
+    let (width, height) = (image.width() as i32, image.height() as i32);
+    let size: Size<i32, Physical> = transform.transform_size((width, height).into());
+    // Pixels are indexed from zero, so the far edges are one short of the size
+    let last: Size<i32, Physical> = (width - 1, height - 1).into();
+
+    let mut upright = RgbaImage::new(size.w as u32, size.h as u32);
+    for (x, y, pixel) in image.enumerate_pixels() {
+        let point: Point<i32, Physical> = (x as i32, y as i32).into();
+        let point = transform.transform_point_in(point, &last);
+        upright.put_pixel(point.x as u32, point.y as u32, *pixel);
+    }
+    upright
+}
+
+/// `path` with `-<output>` added before the extension.
+fn output_path(path: &Path, output: &str) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "screenshot".to_string());
+    path.with_file_name(format!("{}-{}.png", stem, output))
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::*;
+
+    const RED: Rgba<u8> = Rgba([255, 0, 0, 255]);
+
+    /// A 3x2 frame, black but for a red top-left pixel.
+    fn frame() -> RgbaImage {
+        let mut frame = RgbaImage::new(3, 2);
+        frame.put_pixel(0, 0, RED);
+        frame
+    }
+
+    #[test]
+    fn rotated_frames_come_out_upright() {
+        let upright = untransform(&frame(), Transform::_90);
+        assert_eq!(upright.dimensions(), (2, 3));
+        assert_eq!(*upright.get_pixel(1, 0), RED);
+
+        let upright = untransform(&frame(), Transform::_270);
+        assert_eq!(upright.dimensions(), (2, 3));
+        assert_eq!(*upright.get_pixel(0, 2), RED);
+
+        let upright = untransform(&frame(), Transform::_180);
+        assert_eq!(upright.dimensions(), (3, 2));
+        assert_eq!(*upright.get_pixel(2, 1), RED);
+    }
+
+    #[test]
+    fn winit_frames_are_flipped_back() {
+        let upright = untransform(&frame(), Transform::Flipped180);
+        assert_eq!(upright.dimensions(), (3, 2));
+        assert_eq!(*upright.get_pixel(0, 1), RED);
+    }
+}
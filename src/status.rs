@@ -0,0 +1,88 @@
+use serde::Serialize;
+use smithay::wayland::seat::WaylandFocus;
+
+use crate::{App, Display, PanelId};
+
+/// The state of one panel, as reported by the `status` query.
+#[derive(Serialize)]
+pub struct PanelStatus {
+    id: PanelId,
+    /// The `Display` variant, such as `Webpage`
+    kind: &'static str,
+    /// Whether its child process is alive. Left out for panels drawn by the compositor
+    /// itself, and for containers.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    running: Option<bool>,
+    /// Where its window is mapped, as `[x, y, width, height]`. Missing while it has no
+    /// window on screen.
+    geometry: Option<(i32, i32, i32, i32)>,
+    /// When its process was last restarted after exiting, in RFC 3339
+    last_restart: Option<String>,
+}
+
+impl App {
+    /// Every panel of every top-level display, parents before their children, in id
+    /// order. Mirrored copies follow the display they copy, and copies of the default
+    /// display come last.
+    pub(crate) fn status(&self) -> Vec<PanelStatus> {
+        let mut ids: Vec<&u32> = self.displays.keys().collect();
+        ids.sort();
+
+        let mut panels = Vec::new();
+        for id in ids {
+            let display = &self.displays[id].display;
+            self.collect_status(PanelId::root(*id), display, &mut panels);
+            for (mirror_id, _) in self.mirrors_of(*id) {
+                self.collect_status(mirror_id, display, &mut panels);
+            }
+        }
+
+        if let Some(display) = &self.default_display {
+            let mut outputs: Vec<&String> = self.default_rects.keys().collect();
+            outputs.sort();
+            for output in outputs {
+                self.collect_status(PanelId::fallback(output), display, &mut panels);
+            }
+        }
+        panels
+    }
+
+    fn collect_status(&self, id: PanelId, display: &Display, panels: &mut Vec<PanelStatus>) {
+        let process = self.process_state(&id);
+        // A panel that gave up restarting, or never spawned, has neither a process nor
+        // a pending restart
+        let spawns_process = matches!(
+            display,
+            Display::Webpage { .. } | Display::Command { .. } | Display::Video { .. }
+        );
+        let running = process
+            .map(|(running, _)| running)
+            .or(spawns_process.then_some(false));
+
+        let geometry = self.space.elements().find_map(|window| {
+            let surface = window.wl_surface()?;
+            if self.panel_for_surface(&surface).as_ref() != Some(&id) {
+                return None;
+            }
+            let rect = self.space.element_geometry(window)?;
+            Some((rect.loc.x, rect.loc.y, rect.size.w, rect.size.h))
+        });
+
+        panels.push(PanelStatus {
+            id: id.clone(),
+            kind: display.kind(),
+            running,
+            geometry,
+            last_restart: process
+                .and_then(|(_, last_restart)| last_restart)
+                .map(|time| time.to_rfc3339()),
+        });
+
+        for (index, child) in display.children().iter().enumerate() {
+            self.collect_status(id.child(index), child, panels);
+        }
+        if let Some((child_id, child)) = self.scheduled_child(&id) {
+            self.collect_status(child_id, child, panels);
+        }
+    }
+}
@@ -0,0 +1,303 @@
+use std::{fs, io, path::Path};
+
+use fontdue::{Font, FontSettings};
+use serde::{Deserialize, Serialize};
+use smithay::backend::renderer::Color32F;
+
+/// Tried in order when a panel doesn't name a font.
+const DEFAULT_FONTS: [&str; 4] = [
+    "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/TTF/DejaVuSans.ttf",
+    "/usr/share/fonts/noto/NotoSans-Regular.ttf",
+];
+
+/// The largest text size allowed, in pixels per em. Glyphs are rasterized whole, so much
+/// bigger sizes would allocate gigabytes for a single character.
+pub const MAX_SIZE: f32 = 1024.0;
+
+/// Loads the font at `path`, or the first of [`DEFAULT_FONTS`] that exists.
+pub fn load_font(path: Option<&Path>) -> Result<Font, String> {
+    let path = match path {
+        Some(path) => path,
+        None => DEFAULT_FONTS
+            .iter()
+            .map(Path::new)
+            .find(|path| path.exists())
+            .ok_or_else(|| "no default font found, set `font` on the panel".to_string())?,
+    };
+
+    let data = fs::read(path).map_err(|err: io::Error| format!("'{}': {}", path.display(), err))?;
+    Font::from_bytes(data, FontSettings::default())
+        .map_err(|err| format!("'{}': {}", path.display(), err))
+}
+
+/// A line of text rasterized into RGBA bytes.
+pub struct Rendered {
+    pub width: i32,
+    pub height: i32,
+    pub pixels: Vec<u8>,
+}
+
+/// Rasterizes `text` on one line as large as fits in `width` by `height`, using most of
+/// the height unless the text is too wide for it.
+pub fn render_fitted(
+    font: &Font,
+    text: &str,
+    width: i32,
+    height: i32,
+    color: Color32F,
+) -> Rendered {
+    let px = height.max(1) as f32 * 0.8;
+    let natural = measure(font, text, px);
+    let px = if natural > width as f32 && natural > 0.0 {
+        px * width as f32 / natural
+    } else {
+        px
+    };
+
+    render(font, text, px.max(1.0), color)
+}
+
+fn measure(font: &Font, text: &str, px: f32) -> f32 {
+    text.chars()
+        .map(|glyph| font.metrics(glyph, px).advance_width)
+        .sum()
+}
+
+/// Rasterizes `text` at `px` pixels per em. `color` is pre-multiplied, and each pixel is
+/// scaled by the glyph coverage so edges blend smoothly.
+fn render(font: &Font, text: &str, px: f32, color: Color32F) -> Rendered {
+    let (ascent, descent) = font
+        .horizontal_line_metrics(px)
+        .map(|metrics| (metrics.ascent, metrics.descent))
+        .unwrap_or((px, 0.0));
+
+    let mut rendered = Rendered::blank(
+        measure(font, text, px).ceil().max(1.0) as i32,
+        (ascent - descent).ceil().max(1.0) as i32,
+    );
+    let baseline = ascent.round() as i32;
+
+    let mut pen = 0.0;
+    for glyph in text.chars() {
+        pen += rendered.draw_glyph(font, glyph, px, (pen, baseline), Style::default(), color);
+    }
+
+    rendered
+}
+
+/// How a paragraph of [`render_block`] text is lined up across the panel.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum TextAlign {
+    Left,
+    #[default]
+    Center,
+    Right,
+}
+
+/// Emphasis from markdown, applied by slanting and thickening the regular font.
+#[derive(Clone, Copy, PartialEq, Default)]
+struct Style {
+    bold: bool,
+    italic: bool,
+}
+
+/// How far italic glyphs lean, in pixels across per pixel up.
+const ITALIC_SLANT: f32 = 0.2;
+
+/// Splits `line` into characters with the emphasis markdown gives them, `**bold**` and
+/// `*italic*`. A backslash keeps the next character as written.
+fn parse_markdown(line: &str) -> Vec<(char, Style)> {
+    let mut styled = Vec::new();
+    let mut style = Style::default();
+    let mut chars = line.chars().peekable();
+
+    while let Some(glyph) = chars.next() {
+        match glyph {
+            '\\' if chars.peek().is_some() => {
+                styled.extend(chars.next().map(|escaped| (escaped, style)))
+            }
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                style.bold = !style.bold;
+            }
+            '*' => style.italic = !style.italic,
+            _ => styled.push((glyph, style)),
+        }
+    }
+
+    styled
+}
+
+/// Breaks `paragraph` into lines no wider than `width`, at spaces where possible and
+/// anywhere within words too long for a line of their own.
+fn wrap(font: &Font, paragraph: &[(char, Style)], px: f32, width: f32) -> Vec<Vec<(char, Style)>> {
+    let advance = |glyph: char| font.metrics(glyph, px).advance_width;
+    let mut lines = Vec::new();
+    let mut line: Vec<(char, Style)> = Vec::new();
+    let mut line_width = 0.0;
+
+    for word in paragraph.split_inclusive(|(glyph, _)| *glyph == ' ') {
+        let word_width: f32 = word.iter().map(|(glyph, _)| advance(*glyph)).sum();
+        // Trailing spaces may hang past the edge
+        let visible: f32 = word
+            .iter()
+            .filter(|(glyph, _)| *glyph != ' ')
+            .map(|(glyph, _)| advance(*glyph))
+            .sum();
+
+        if line_width + visible > width && !line.is_empty() {
+            lines.push(std::mem::take(&mut line));
+            line_width = 0.0;
+        }
+
+        if visible <= width {
+            line.extend_from_slice(word);
+            line_width += word_width;
+            continue;
+        }
+
+        for &(glyph, style) in word {
+            let glyph_width = advance(glyph);
+            if line_width + glyph_width > width && !line.is_empty() {
+                lines.push(std::mem::take(&mut line));
+                line_width = 0.0;
+            }
+            line.push((glyph, style));
+            line_width += glyph_width;
+        }
+    }
+
+    lines.push(line);
+    lines
+}
+
+/// Rasterizes `content` wrapped to `width` by `height` at `px` pixels per em, over
+/// `background`. Each `\n` starts a new paragraph, and markdown bold and italic are
+/// honored. Text is centered vertically while it fits, and otherwise runs from the top
+/// with whatever doesn't fit cut off at the bottom.
+pub fn render_block(
+    font: &Font,
+    content: &str,
+    (width, height): (i32, i32),
+    px: f32,
+    align: TextAlign,
+    color: Color32F,
+    background: Color32F,
+) -> Rendered {
+    let (ascent, descent, line_gap) = font
+        .horizontal_line_metrics(px)
+        .map(|metrics| (metrics.ascent, metrics.descent, metrics.line_gap))
+        .unwrap_or((px, 0.0, 0.0));
+    let line_height = ascent - descent + line_gap;
+
+    let lines: Vec<Vec<(char, Style)>> = content
+        .lines()
+        .flat_map(|paragraph| wrap(font, &parse_markdown(paragraph), px, width as f32))
+        .collect();
+
+    let mut rendered = Rendered::blank(width.max(1), height.max(1));
+    let block_height = line_height * lines.len() as f32;
+    let top = ((height as f32 - block_height) / 2.0).max(0.0);
+
+    for (index, line) in lines.iter().enumerate() {
+        let baseline = (top + line_height * index as f32 + ascent).round() as i32;
+        // Lines starting below the panel have nothing left to show
+        if baseline as f32 - ascent >= height as f32 {
+            break;
+        }
+
+        let line_width: f32 = line
+            .iter()
+            .map(|(glyph, _)| font.metrics(*glyph, px).advance_width)
+            .sum();
+        let mut pen = match align {
+            TextAlign::Left => 0.0,
+            TextAlign::Center => (width as f32 - line_width) / 2.0,
+            TextAlign::Right => width as f32 - line_width,
+        };
+        for &(glyph, style) in line {
+            pen += rendered.draw_glyph(font, glyph, px, (pen, baseline), style, color);
+        }
+    }
+
+    rendered.fill_behind(background);
+    rendered
+}
+
+impl Rendered {
+    fn blank(width: i32, height: i32) -> Self {
+        Rendered {
+            width,
+            height,
+            pixels: vec![0u8; (width * height * 4) as usize],
+        }
+    }
+
+    /// Draws `glyph` with its origin at `pen` across and `baseline` down, clipped to the
+    /// buffer. Returns how far to move the pen along.
+    fn draw_glyph(
+        &mut self,
+        font: &Font,
+        glyph: char,
+        px: f32,
+        (pen, baseline): (f32, i32),
+        style: Style,
+        color: Color32F,
+    ) -> f32 {
+        let (metrics, coverage) = font.rasterize(glyph, px);
+        let left = (pen + metrics.xmin as f32).round() as i32;
+        let top = baseline - metrics.ymin - metrics.height as i32;
+        // Bold is the glyph drawn again a little to the right
+        let strokes = if style.bold {
+            vec![0, (px / 24.0).ceil() as i32]
+        } else {
+            vec![0]
+        };
+        let [r, g, b, a] = color.components();
+
+        for row in 0..metrics.height as i32 {
+            let y = top + row;
+            let slant = if style.italic {
+                ((baseline - y) as f32 * ITALIC_SLANT).round() as i32
+            } else {
+                0
+            };
+
+            for column in 0..metrics.width as i32 {
+                let alpha = coverage[(row * metrics.width as i32 + column) as usize] as f32 / 255.0;
+                for stroke in &strokes {
+                    let x = left + column + slant + stroke;
+                    if x < 0 || y < 0 || x >= self.width || y >= self.height {
+                        continue;
+                    }
+
+                    let offset = ((y * self.width + x) * 4) as usize;
+                    // Glyph boxes can overlap, so keep whichever covers the pixel more
+                    if alpha * a * 255.0 <= self.pixels[offset + 3] as f32 {
+                        continue;
+                    }
+                    for (channel, value) in
+                        self.pixels[offset..offset + 4].iter_mut().zip([r, g, b, a])
+                    {
+                        *channel = (value * alpha * 255.0).round() as u8;
+                    }
+                }
+            }
+        }
+
+        metrics.advance_width
+    }
+
+    /// Puts `background`, which is pre-multiplied, behind everything drawn so far.
+    fn fill_behind(&mut self, background: Color32F) {
+        let background = background.components().map(|channel| channel * 255.0);
+        for pixel in self.pixels.chunks_exact_mut(4) {
+            let uncovered = 1.0 - pixel[3] as f32 / 255.0;
+            for (channel, behind) in pixel.iter_mut().zip(background) {
+                *channel = (*channel as f32 + behind * uncovered).round().min(255.0) as u8;
+            }
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use std::sync::atomic::Ordering;
+
+use smithay::{
+    backend::{
+        renderer::{damage::OutputDamageTracker, gles::GlesRenderer},
+        winit::{self, WinitEvent, WinitGraphicsBackend},
+    },
+    output::{Mode, Output, PhysicalProperties, Subpixel},
+    utils::Transform,
+};
+
+use tracing::{info, warn};
+
+use crate::App;
+
+/// Runs the compositor inside a window on an existing desktop session.
+pub struct Backend {
+    graphics: WinitGraphicsBackend<GlesRenderer>,
+    output: Output,
+    damage_tracker: OutputDamageTracker,
+}
+
+impl Backend {
+    pub fn new(app: &mut App) -> Self {
+        let (graphics, events) =
+            winit::init::<GlesRenderer>().expect("Failed to initialise winit backend");
+
+        let output = Output::new(
+            "winit".to_string(),
+            PhysicalProperties {
+                size: (0, 0).into(),
+                subpixel: Subpixel::Unknown,
+                make: "screens".into(),
+                model: "winit".into(),
+            },
+        );
+        let mode = Mode {
+            size: graphics.window_size(),
+            refresh: 60_000,
+        };
+        output.create_global::<App>(&app.display_handle);
+        output.change_current_state(
+            Some(mode),
+            Some(Transform::Flipped180),
+            None,
+            Some((0, 0).into()),
+        );
+        output.set_preferred(mode);
+        app.space.map_output(&output, (0, 0));
+
+        // Window events are handled as they arrive. Closing the window shuts the compositor
+        // down.
+        let resized = output.clone();
+        app.loop_handle
+            .insert_source(events, move |event, _, app: &mut App| match event {
+                WinitEvent::Resized { size, .. } => {
+                    let mode = Mode {
+                        size,
+                        refresh: 60_000,
+                    };
+                    resized.change_current_state(Some(mode), None, None, None);
+                    resized.set_preferred(mode);
+                    // Panels are laid out against the window, so follow it when it changes size
+                    app.outputs_changed();
+                }
+                WinitEvent::Input(event) => app.handle_input(event),
+                WinitEvent::CloseRequested => {
+                    info!("Window closed, shutting down");
+                    app.shutdown.store(true, Ordering::Relaxed);
+                }
+                WinitEvent::Focus(_) | WinitEvent::Redraw => {}
+            })
+            .expect("Failed to watch window events");
+
+        let damage_tracker = OutputDamageTracker::from_output(&output);
+        Backend {
+            graphics,
+            output,
+            damage_tracker,
+        }
+    }
+
+    pub fn render(&mut self, app: &mut App) {
+        // An unknown age means the buffer's contents can't be trusted, so draw it all
+        let age = self.graphics.buffer_age().unwrap_or(0);
+
+        let frame = {
+            let (renderer, mut framebuffer) = match self.graphics.bind() {
+                Ok(bound) => bound,
+                Err(err) => {
+                    warn!("Failed to bind winit framebuffer: {}", err);
+                    return;
+                }
+            };
+
+            app.render_frame(
+                renderer,
+                &mut framebuffer,
+                &mut self.damage_tracker,
+                age,
+                &self.output,
+            )
+        };
+
+        match frame {
+            Ok(Some(frame)) => {
+                if let Err(err) = self.graphics.submit(Some(&frame.damage)) {
+                    warn!("Failed to submit frame: {}", err);
+                }
+            }
+            Ok(None) => {}
+            Err(err) => {
+                warn!("Failed to render frame: {}", err);
+                return;
+            }
+        }
+
+        // Sent even when nothing changed, so clients waiting on a callback can draw
+        app.send_frames(&self.output);
+        app.space.refresh();
+    }
+}